@@ -0,0 +1,39 @@
+//! Compare instruction decoders on day 9's BOOST program in sensor-boost
+//! mode, which runs for a few hundred thousand instructions.
+//!
+//! Run with `cargo bench`.
+
+use std::time::{Duration, Instant};
+
+use advent2019::{Decoder, Event, Machine};
+
+const RUNS: u32 = 10;
+
+fn boost(program: &[i64], decoder: Decoder) -> i64 {
+    let mut machine = Machine::new(program.to_vec()).with_decoder(decoder);
+    machine.provide_input(2);
+    match machine.run_until_event().unwrap() {
+        Event::Output(v) => v,
+        other => panic!("Unexpected event: {:?}", other),
+    }
+}
+
+fn main() {
+    let program = Machine::from_file("data/09").state().to_vec();
+    let expected = boost(&program, Decoder::Parse);
+    let mut baseline = None;
+    for &decoder in &[Decoder::Parse, Decoder::Table, Decoder::Cached] {
+        let start = Instant::now();
+        for _ in 0..RUNS {
+            assert_eq!(boost(&program, decoder), expected);
+        }
+        let per_run = start.elapsed() / RUNS;
+        let base: Duration = *baseline.get_or_insert(per_run);
+        println!(
+            "{:<8} {:>10.3} ms/run  {:>5.2}x",
+            format!("{:?}", decoder),
+            per_run.as_secs_f64() * 1000.0,
+            base.as_secs_f64() / per_run.as_secs_f64()
+        );
+    }
+}
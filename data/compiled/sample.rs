@@ -0,0 +1,216 @@
+// Generated by advent2019::compile from a 34-word program. Do not edit.
+
+use crate::{Arithmetic, Event, Machine, MachineError};
+
+#[allow(dead_code)]
+enum Next {
+    Go(usize),
+    Output(usize, i64),
+    NeedInput,
+    Halt,
+}
+
+#[allow(dead_code)]
+fn addr(m: &Machine, a: i64) -> Option<usize> {
+    if a >= 0 && (a as usize) < m.memory_limit() {
+        Some(a as usize)
+    } else {
+        None
+    }
+}
+
+#[allow(dead_code)]
+fn load(m: &Machine, a: i64) -> Option<i64> {
+    addr(m, a).map(|a| m.peek(a))
+}
+
+#[allow(dead_code)]
+fn store(m: &mut Machine, a: usize, val: i64, dirty: &mut bool) {
+    if a < CODE.len() && CODE[a] {
+        *dirty = true;
+    }
+    m.poke(a, val);
+}
+
+fn unchanged(m: &Machine, at: usize, width: usize) -> bool {
+    (at..at + width).all(|a| load(m, a as i64) == Some(PROGRAM[a]))
+}
+
+/// Run until the machine wants input it has not been given, produces an
+/// output, or halts.
+pub fn run(m: &mut Machine) -> Result<Event, MachineError> {
+    if m.arithmetic() == Arithmetic::Big {
+        return m.run_until_event();
+    }
+    let mut ip = m.ip();
+    let mut rb = m.relative_base();
+    let mut dirty = (0..PROGRAM.len()).any(|a| CODE[a] && !unchanged(m, a, 1));
+    loop {
+        match step(m, ip, &mut rb, &mut dirty) {
+            Some(Next::Go(next)) => ip = next,
+            Some(Next::Output(next, val)) => {
+                m.set_ip(next);
+                m.set_relative_base(rb);
+                return Ok(Event::Output(val));
+            }
+            Some(Next::NeedInput) => {
+                m.set_ip(ip);
+                m.set_relative_base(rb);
+                return Ok(Event::NeedInput);
+            }
+            Some(Next::Halt) => {
+                m.set_ip(ip);
+                m.set_relative_base(rb);
+                return match load(m, 0) {
+                    Some(val) => Ok(Event::Halted(val)),
+                    // Let the interpreter report the fault.
+                    None => m.run_until_event(),
+                };
+            }
+            None => {
+                m.set_ip(ip);
+                m.set_relative_base(rb);
+                let event = m.step_event()?;
+                // The interpreter may have written anywhere.
+                dirty = true;
+                ip = m.ip();
+                rb = m.relative_base();
+                if let Some(event) = event {
+                    return Ok(event);
+                }
+            }
+        }
+    }
+}
+
+pub static PROGRAM: [i64; 34] = [
+    3, 30, 1002, 30, 4, 31, 107, 0,
+    31, 32, 4, 32, 109, 33, 204, 0,
+    21201, 0, -1, 0, 1205, 0, 14, 101,
+    1, 28, 28, 104, 7, 99, 0, 0,
+    0, 3,
+];
+
+/// Which words belong to compiled instructions.
+static CODE: [bool; 34] = [
+    true, true, true, true, true, true, true, true,
+    true, true, true, true, true, true, true, true,
+    true, true, true, true, true, true, true, true,
+    true, true, true, true, true, true, false, false,
+    false, false,
+];
+
+#[allow(unused_parens, unused_variables, clippy::all)]
+fn step(m: &mut Machine, ip: usize, rb: &mut i64, dirty: &mut bool) -> Option<Next> {
+    match ip {
+        // in 30
+        0 => {
+            if *dirty && !unchanged(m, 0, 2) {
+                return None;
+            }
+            let dst = addr(m, 30)?;
+            let val = match m.take_input() {
+                Some(val) => val,
+                None => return Some(Next::NeedInput),
+            };
+            store(m, dst, val, dirty);
+            Some(Next::Go(2))
+        }
+        // mul 30, #4, 31
+        2 => {
+            if *dirty && !unchanged(m, 2, 4) {
+                return None;
+            }
+            let (a, b) = (load(m, 30)?, (4i64));
+            let val = a.checked_mul(b)?;
+            let dst = addr(m, 31)?;
+            store(m, dst, val, dirty);
+            Some(Next::Go(6))
+        }
+        // lt #0, 31, 32
+        6 => {
+            if *dirty && !unchanged(m, 6, 4) {
+                return None;
+            }
+            let (a, b) = ((0i64), load(m, 31)?);
+            let val = (a < b) as i64;
+            let dst = addr(m, 32)?;
+            store(m, dst, val, dirty);
+            Some(Next::Go(10))
+        }
+        // out 32
+        10 => {
+            if *dirty && !unchanged(m, 10, 2) {
+                return None;
+            }
+            Some(Next::Output(12, load(m, 32)?))
+        }
+        // arb #33
+        12 => {
+            if *dirty && !unchanged(m, 12, 2) {
+                return None;
+            }
+            *rb = rb.checked_add((33i64))?;
+            Some(Next::Go(14))
+        }
+        // out @0
+        14 => {
+            if *dirty && !unchanged(m, 14, 2) {
+                return None;
+            }
+            Some(Next::Output(16, load(m, rb.checked_add(0)?)?))
+        }
+        // add @0, #-1, @0
+        16 => {
+            if *dirty && !unchanged(m, 16, 4) {
+                return None;
+            }
+            let (a, b) = (load(m, rb.checked_add(0)?)?, (-1i64));
+            let val = a.checked_add(b)?;
+            let dst = addr(m, rb.checked_add(0)?)?;
+            store(m, dst, val, dirty);
+            Some(Next::Go(20))
+        }
+        // jt @0, #14
+        20 => {
+            if *dirty && !unchanged(m, 20, 3) {
+                return None;
+            }
+            let (cond, to) = (load(m, rb.checked_add(0)?)?, (14i64));
+            if cond != 0 {
+                if to < 0 {
+                    return None;
+                }
+                Some(Next::Go(to as usize))
+            } else {
+                Some(Next::Go(23))
+            }
+        }
+        // add #1, 28, 28
+        23 => {
+            if *dirty && !unchanged(m, 23, 4) {
+                return None;
+            }
+            let (a, b) = ((1i64), load(m, 28)?);
+            let val = a.checked_add(b)?;
+            let dst = addr(m, 28)?;
+            store(m, dst, val, dirty);
+            Some(Next::Go(27))
+        }
+        // out #7
+        27 => {
+            if *dirty && !unchanged(m, 27, 2) {
+                return None;
+            }
+            Some(Next::Output(29, (7i64)))
+        }
+        // hlt
+        29 => {
+            if *dirty && !unchanged(m, 29, 1) {
+                return None;
+            }
+            Some(Next::Halt)
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,356 @@
+//! Ready-made input sources and output sinks, so callers don't have to write
+//! their own closures for `Machine::new_with_adapters`.
+//!
+//! ```
+//! use advent2019::adapters::{Collector, IterInput};
+//! use advent2019::Machine;
+//!
+//! // Echo one value.
+//! let collected = Collector::new();
+//! let mut machine = Machine::new_with_adapters(
+//!     vec![3, 5, 4, 5, 99, 0],
+//!     IterInput::new(vec![42]),
+//!     collected.sink(),
+//! );
+//! machine.run();
+//! assert_eq!(collected.values(), vec![42]);
+//! ```
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+use crate::{Event, Machine, MachineError};
+
+/// Somewhere a machine's input comes from. `None` means there is no more.
+pub trait Input: Send {
+    fn read(&mut self) -> Option<i64>;
+}
+
+/// Somewhere a machine's output goes.
+pub trait Output: Send {
+    fn write(&mut self, val: i64);
+}
+
+impl<F: FnMut() -> Option<i64> + Send> Input for F {
+    fn read(&mut self) -> Option<i64> {
+        self()
+    }
+}
+
+impl<F: FnMut(i64) + Send> Output for F {
+    fn write(&mut self, val: i64) {
+        self(val)
+    }
+}
+
+/// Input from any iterator of values.
+pub struct IterInput<I>(I);
+
+impl<I: Iterator<Item = i64>> IterInput<I> {
+    pub fn new(values: impl IntoIterator<IntoIter = I>) -> Self {
+        IterInput(values.into_iter())
+    }
+}
+
+impl<I: Iterator<Item = i64> + Send> Input for IterInput<I> {
+    fn read(&mut self) -> Option<i64> {
+        self.0.next()
+    }
+}
+
+/// Input of the character codes of `text`.
+pub fn ascii_input(text: &str) -> IterInput<std::vec::IntoIter<i64>> {
+    IterInput::new(text.bytes().map(i64::from).collect::<Vec<_>>())
+}
+
+/// Input of the integers in a file, separated by commas or whitespace.
+pub fn file_input(path: impl AsRef<Path>) -> io::Result<IterInput<std::vec::IntoIter<i64>>> {
+    let text = fs::read_to_string(path)?;
+    let values = text
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            word.parse().map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("Bad value: {}", word))
+            })
+        })
+        .collect::<io::Result<Vec<i64>>>()?;
+    Ok(IterInput::new(values))
+}
+
+/// Collects output values; keep the `Collector` and give the machine a
+/// `sink()`.
+#[derive(Debug, Clone, Default)]
+pub struct Collector(Arc<Mutex<Vec<i64>>>);
+
+impl Collector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn sink(&self) -> impl Output {
+        let values = self.0.clone();
+        move |val| values.lock().unwrap().push(val)
+    }
+
+    /// Everything collected so far.
+    pub fn values(&self) -> Vec<i64> {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// The collected values as ASCII text. Values that aren't ASCII are
+    /// written as decimal numbers on their own line.
+    pub fn text(&self) -> String {
+        ascii_text(&self.0.lock().unwrap())
+    }
+}
+
+/// Render output values as ASCII, with non-ASCII values as numbers on their
+/// own line.
+pub fn ascii_text(values: &[i64]) -> String {
+    let mut text = String::new();
+    for &val in values {
+        match val {
+            0..=127 => text.push(val as u8 as char),
+            _ => {
+                if !text.is_empty() && !text.ends_with('\n') {
+                    text.push('\n');
+                }
+                text.push_str(&format!("{}\n", val));
+            }
+        }
+    }
+    text
+}
+
+/// Input of typed text: each line read from `R` is fed in as character
+/// codes, ending with 10 for the newline.
+pub struct AsciiInput<R> {
+    reader: R,
+    pending: VecDeque<i64>,
+}
+
+impl<R: BufRead> AsciiInput<R> {
+    pub fn new(reader: R) -> Self {
+        AsciiInput {
+            reader,
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl<R: BufRead + Send> Input for AsciiInput<R> {
+    fn read(&mut self) -> Option<i64> {
+        if self.pending.is_empty() {
+            let mut line = String::new();
+            match self.reader.read_line(&mut line) {
+                Ok(0) | Err(_) => return None,
+                Ok(_) => {}
+            }
+            let line = line.trim_end_matches(&['\r', '\n'][..]);
+            self.pending.extend(line.bytes().map(i64::from));
+            self.pending.push_back(10);
+        }
+        self.pending.pop_front()
+    }
+}
+
+/// Output written to `W` as text, a line at a time. Values that aren't ASCII
+/// are written as decimal numbers on their own line. A partial last line is
+/// written when the output is dropped.
+pub struct AsciiOutput<W: Write> {
+    writer: W,
+    line: String,
+}
+
+impl<W: Write> AsciiOutput<W> {
+    pub fn new(writer: W) -> Self {
+        AsciiOutput {
+            writer,
+            line: String::new(),
+        }
+    }
+
+    fn flush_line(&mut self) {
+        self.writer
+            .write_all(self.line.as_bytes())
+            .and_then(|_| self.writer.flush())
+            .expect("Failed to write output");
+        self.line.clear();
+    }
+}
+
+impl<W: Write + Send> Output for AsciiOutput<W> {
+    fn write(&mut self, val: i64) {
+        match val {
+            10 => {
+                self.line.push('\n');
+                self.flush_line();
+            }
+            0..=127 => self.line.push(val as u8 as char),
+            _ => {
+                if !self.line.is_empty() {
+                    self.line.push('\n');
+                }
+                self.line.push_str(&format!("{}\n", val));
+                self.flush_line();
+            }
+        }
+    }
+}
+
+impl<W: Write> Drop for AsciiOutput<W> {
+    fn drop(&mut self) {
+        if !self.line.is_empty() {
+            self.flush_line();
+        }
+    }
+}
+
+/// Output sent down a channel. Values sent after the receiver has gone are
+/// dropped.
+pub struct ChannelOutput(pub Sender<i64>);
+
+impl Output for ChannelOutput {
+    fn write(&mut self, val: i64) {
+        let _ = self.0.send(val);
+    }
+}
+
+/// Output copied to several sinks, in order.
+#[derive(Default)]
+pub struct Tee(Vec<Box<dyn Output>>);
+
+impl Tee {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, output: impl Output + 'static) -> Self {
+        self.0.push(Box::new(output));
+        self
+    }
+}
+
+impl Output for Tee {
+    fn write(&mut self, val: i64) {
+        for output in self.0.iter_mut() {
+            output.write(val);
+        }
+    }
+}
+
+impl Machine {
+    /// Create a machine reading from `input` and writing to `output`. When
+    /// `input` runs dry the machine fails with `InputExhausted`.
+    pub fn new_with_adapters(
+        data: Vec<i64>,
+        mut input: impl Input + 'static,
+        mut output: impl Output + 'static,
+    ) -> Self {
+        Self::new_with_fallible_io(data, move || input.read(), move |val| output.write(val))
+    }
+
+    /// Run to completion with `inputs` queued, returning everything output.
+    /// Panics on error, as `run` does.
+    pub fn run_with_inputs(&mut self, inputs: &[i64]) -> Vec<i64> {
+        match self.try_run_with_inputs(inputs) {
+            Ok(outputs) => outputs,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Like `run_with_inputs`, failing with `InputExhausted` if the program
+    /// wants more input than it was given. The I/O callbacks are not used.
+    pub fn try_run_with_inputs(&mut self, inputs: &[i64]) -> Result<Vec<i64>, MachineError> {
+        for &val in inputs {
+            self.provide_input(val);
+        }
+        let mut outputs = Vec::new();
+        loop {
+            match self.run_until_event()? {
+                Event::Output(val) => outputs.push(val),
+                Event::Halted(_) => return Ok(outputs),
+                Event::NeedInput => {
+                    let (ip, instr) = (self.ip, self.peek(self.ip));
+                    return Err(MachineError::InputExhausted { ip, instr });
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn sources_and_sinks() {
+    // Read two values and output their sum.
+    let program = vec![3, 11, 3, 12, 1, 11, 12, 13, 4, 13, 99, 0, 0, 0, 0];
+    let collected = Collector::new();
+    let (tx, rx) = std::sync::mpsc::channel();
+    let tee = Tee::new()
+        .with(collected.sink())
+        .with(ChannelOutput(tx))
+        .with(collected.sink());
+    let mut machine = Machine::new_with_adapters(program.clone(), ascii_input("AB"), tee);
+    machine.run();
+    drop(machine);
+    assert_eq!(collected.values(), vec![131, 131]);
+    assert_eq!(rx.iter().collect::<Vec<_>>(), vec![131]);
+
+    let mut machine = Machine::new_with_adapters(program, IterInput::new(vec![1]), |_| ());
+    assert!(matches!(
+        machine.try_run(),
+        Err(MachineError::InputExhausted { ip: 2, .. })
+    ));
+}
+
+#[test]
+fn run_with_inputs_collects_outputs() {
+    let program = vec![3, 11, 3, 12, 1, 11, 12, 13, 4, 13, 99, 0, 0, 0, 0];
+    assert_eq!(
+        Machine::new(program.clone()).run_with_inputs(&[2, 3]),
+        vec![5]
+    );
+    assert!(matches!(
+        Machine::new(program).try_run_with_inputs(&[2]),
+        Err(MachineError::InputExhausted { ip: 2, .. })
+    ));
+}
+
+#[test]
+fn ascii_rendering() {
+    let values: Vec<i64> = "Hi\n"
+        .bytes()
+        .map(i64::from)
+        .chain(vec![1000, 33])
+        .collect();
+    assert_eq!(ascii_text(&values), "Hi\n1000\n!");
+}
+
+#[test]
+fn ascii_terminal() {
+    let mut input = AsciiInput::new(io::Cursor::new("go\r\nstop\n"));
+    let read: Vec<_> = std::iter::from_fn(|| input.read()).collect();
+    assert_eq!(read, vec![103, 111, 10, 115, 116, 111, 112, 10]);
+
+    let written = Arc::new(Mutex::new(Vec::new()));
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+    let mut output = AsciiOutput::new(Shared(written.clone()));
+    for val in "ok\nab".bytes().map(i64::from).chain(vec![4242, 63]) {
+        output.write(val);
+    }
+    assert_eq!(&written.lock().unwrap()[..], b"ok\nab\n4242\n");
+    drop(output);
+    assert_eq!(&written.lock().unwrap()[..], b"ok\nab\n4242\n?");
+}
@@ -0,0 +1,368 @@
+//! A small assembler for Intcode, using the same notation as `disasm`.
+//!
+//! ```text
+//! ; Echo numbers until a zero is read.
+//! loop:   in value
+//!         jf value, #end
+//!         out value
+//!         jt #1, #loop
+//! end:    hlt
+//! value:  db 0
+//! ```
+//!
+//! Each line holds an optional `label:`, then an instruction or directive,
+//! then an optional `;` comment. Operands are position mode by default, `#`
+//! marks immediate mode and `@` marks relative mode. An operand is a number,
+//! a label or constant, or a sum of them like `value+1` or `@arg-2`.
+//!
+//! Directives:
+//!
+//! * `db 1, 2, label` (or `.data`) emits raw words.
+//! * `NAME equ -1` defines a constant, handy for naming relative-base offsets.
+//!   Its value may use any label, but only constants defined above it.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::{Instr, Mode, OpNone, OpOne, OpThree, OpTwo};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    /// 1-based source line.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+enum Item<'a> {
+    Instr(&'a str, Vec<Operand<'a>>),
+    Data(Vec<&'a str>),
+}
+
+struct Operand<'a> {
+    mode: Mode,
+    expr: &'a str,
+}
+
+struct Statement<'a> {
+    line: usize,
+    addr: usize,
+    item: Item<'a>,
+}
+
+/// Assemble source text into a program loadable by `Machine::new`.
+pub fn assemble(src: &str) -> Result<Vec<i64>, AsmError> {
+    let mut symbols = HashMap::new();
+    let mut names = HashSet::new();
+    let mut constants = Vec::new();
+    let mut statements = Vec::new();
+    let mut addr = 0;
+
+    // First pass: find where everything lives.
+    for (ix, raw) in src.lines().enumerate() {
+        let line = ix + 1;
+        let err = |message: String| AsmError { line, message };
+        let mut text = raw.split(';').next().unwrap().trim();
+
+        if let Some(colon) = text.find(':') {
+            let label = text[..colon].trim();
+            check_name(label).map_err(err)?;
+            if !names.insert(label) {
+                return Err(err(format!("Duplicate symbol: {}", label)));
+            }
+            symbols.insert(label, addr as i64);
+            text = text[colon + 1..].trim();
+        }
+        if text.is_empty() {
+            continue;
+        }
+
+        let (head, rest) = split_word(text);
+        let (word, rest2) = split_word(rest);
+        if word.eq_ignore_ascii_case("equ") {
+            check_name(head).map_err(err)?;
+            if !names.insert(head) {
+                return Err(err(format!("Duplicate symbol: {}", head)));
+            }
+            constants.push((line, head, rest2));
+            continue;
+        }
+
+        let args: Vec<&str> = if rest.is_empty() {
+            Vec::new()
+        } else {
+            rest.split(',').map(str::trim).collect()
+        };
+        let mnemonic = head.to_ascii_lowercase();
+        let item = if mnemonic == "db" || mnemonic == ".data" {
+            Item::Data(args)
+        } else {
+            let operands = args
+                .into_iter()
+                .map(parse_operand)
+                .collect::<Result<_, _>>()
+                .map_err(err)?;
+            Item::Instr(head, operands)
+        };
+        let width = match &item {
+            Item::Instr(_, operands) => operands.len() + 1,
+            Item::Data(words) => words.len(),
+        };
+        statements.push(Statement { line, addr, item });
+        addr += width;
+    }
+
+    // Constants may refer to labels, and to constants defined above them;
+    // later constants are not in `symbols` yet, so are unknown here.
+    for (line, name, expr) in constants {
+        let val = eval(expr, &symbols).map_err(|message| AsmError { line, message })?;
+        symbols.insert(name, val);
+    }
+
+    // Second pass: emit words.
+    let mut program = Vec::with_capacity(addr);
+    for stmt in statements {
+        let line = stmt.line;
+        let err = |message: String| AsmError { line, message };
+        debug_assert_eq!(program.len(), stmt.addr);
+        match stmt.item {
+            Item::Data(words) => {
+                for word in words {
+                    program.push(eval(word, &symbols).map_err(err)?);
+                }
+            }
+            Item::Instr(mnemonic, operands) => {
+                let modes: Vec<Mode> = operands.iter().map(|op| op.mode).collect();
+                let instr = build_instr(mnemonic, &modes).map_err(err)?;
+                if instr.writes_immediate() {
+                    return Err(err(format!("{} cannot write to an immediate", mnemonic)));
+                }
+                program.push(instr.encode());
+                for op in operands {
+                    program.push(eval(op.expr, &symbols).map_err(err)?);
+                }
+            }
+        }
+    }
+    Ok(program)
+}
+
+fn split_word(text: &str) -> (&str, &str) {
+    match text.find(char::is_whitespace) {
+        Some(ix) => (&text[..ix], text[ix..].trim()),
+        None => (text, ""),
+    }
+}
+
+fn check_name(name: &str) -> Result<(), String> {
+    let mut chars = name.chars();
+    let ok = chars
+        .next()
+        .map(|c| c.is_ascii_alphabetic() || c == '_')
+        .unwrap_or(false)
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if ok {
+        Ok(())
+    } else {
+        Err(format!("Bad symbol name: {:?}", name))
+    }
+}
+
+fn parse_operand(text: &str) -> Result<Operand<'_>, String> {
+    let (mode, expr) = if let Some(expr) = text.strip_prefix('#') {
+        (Mode::Immediate, expr)
+    } else if let Some(expr) = text.strip_prefix('@') {
+        (Mode::Relative, expr)
+    } else {
+        (Mode::Position, text)
+    };
+    if expr.trim().is_empty() {
+        return Err(format!("Missing operand in {:?}", text));
+    }
+    Ok(Operand {
+        mode,
+        expr: expr.trim(),
+    })
+}
+
+/// Evaluate a sum like `3`, `-1`, `label` or `label+2-base`.
+fn eval(expr: &str, symbols: &HashMap<&str, i64>) -> Result<i64, String> {
+    let mut total = 0i64;
+    let mut sign = 1;
+    let add = |total: i64, sign: i64, term: &str| {
+        term_value(term, symbols)?
+            .checked_mul(sign)
+            .and_then(|val| total.checked_add(val))
+            .ok_or_else(|| format!("Overflow in expression: {:?}", expr))
+    };
+    let mut term = String::new();
+    let mut chars = expr.chars().filter(|c| !c.is_whitespace());
+    loop {
+        match chars.next() {
+            Some(c) if (c == '+' || c == '-') && !term.is_empty() => {
+                total = add(total, sign, &term)?;
+                term.clear();
+                sign = if c == '+' { 1 } else { -1 };
+            }
+            Some('-') => sign = -sign,
+            Some('+') => {}
+            Some(c) => term.push(c),
+            None => {
+                if term.is_empty() {
+                    return Err(format!("Bad expression: {:?}", expr));
+                }
+                return add(total, sign, &term);
+            }
+        }
+    }
+}
+
+fn term_value(term: &str, symbols: &HashMap<&str, i64>) -> Result<i64, String> {
+    if term.starts_with(|c: char| c.is_ascii_digit()) {
+        term.parse().map_err(|_| format!("Bad number: {:?}", term))
+    } else {
+        symbols
+            .get(term)
+            .cloned()
+            .ok_or_else(|| format!("Unknown symbol: {}", term))
+    }
+}
+
+fn build_instr(mnemonic: &str, modes: &[Mode]) -> Result<Instr, String> {
+    let arity = |n: usize| {
+        if modes.len() == n {
+            Ok(())
+        } else {
+            Err(format!(
+                "{} takes {} operands, got {}",
+                mnemonic,
+                n,
+                modes.len()
+            ))
+        }
+    };
+    let instr = match mnemonic.to_ascii_lowercase().as_str() {
+        "hlt" => {
+            arity(0)?;
+            Instr::NoneArg(OpNone::Exit)
+        }
+        "in" | "out" | "arb" => {
+            arity(1)?;
+            let op = match mnemonic.to_ascii_lowercase().as_str() {
+                "in" => OpOne::Input,
+                "out" => OpOne::Output,
+                _ => OpOne::AdjustRelativeBase,
+            };
+            Instr::OneArg(op, modes[0])
+        }
+        "jt" | "jf" => {
+            arity(2)?;
+            let op = if mnemonic.eq_ignore_ascii_case("jt") {
+                OpTwo::JumpIfTrue
+            } else {
+                OpTwo::JumpIfFalse
+            };
+            Instr::TwoArg(op, (modes[0], modes[1]))
+        }
+        "add" | "mul" | "lt" | "eq" => {
+            arity(3)?;
+            let op = match mnemonic.to_ascii_lowercase().as_str() {
+                "add" => OpThree::Add,
+                "mul" => OpThree::Mul,
+                "lt" => OpThree::LessThan,
+                _ => OpThree::Equals,
+            };
+            Instr::ThreeArg(op, (modes[0], modes[1], modes[2]))
+        }
+        _ => return Err(format!("Unknown mnemonic: {}", mnemonic)),
+    };
+    Ok(instr)
+}
+
+#[test]
+fn assembles_labels_and_data() {
+    let src = "
+        ; Echo numbers until a zero is read.
+        loop:   in value
+                jf value, #end
+                out value
+                jt #1, #loop
+        end:    hlt
+        value:  db 0
+    ";
+    let program = assemble(src).unwrap();
+    assert_eq!(program, vec![3, 11, 1006, 11, 10, 4, 11, 1105, 1, 0, 99, 0]);
+}
+
+#[test]
+fn assembles_relative_offsets() {
+    // The day 9 quine.
+    let src = "
+        PREV equ -1
+        start:  arb #1
+                out @PREV
+                add counter, #1, counter
+                eq counter, #16, flag
+                jf flag, #start
+                hlt
+        counter equ 100
+        flag equ counter+1
+    ";
+    let program = assemble(src).unwrap();
+    assert_eq!(
+        program,
+        vec![109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99]
+    );
+}
+
+#[test]
+fn reports_errors_with_line() {
+    let err = assemble("hlt\nadd 1, 2, #3").unwrap_err();
+    assert_eq!(err.line, 2);
+    let err = assemble("jt #1, #nowhere").unwrap_err();
+    assert_eq!(err.message, "Unknown symbol: nowhere");
+    let err = assemble("db 9223372036854775807+1").unwrap_err();
+    assert_eq!(
+        err.message,
+        "Overflow in expression: \"9223372036854775807+1\""
+    );
+}
+
+#[test]
+fn rejects_forward_constant_references() {
+    let err = assemble("a equ b+1\nb equ 5\nout #a\nhlt").unwrap_err();
+    assert_eq!(err.line, 1);
+    assert_eq!(err.message, "Unknown symbol: b");
+    assert_eq!(
+        assemble("b equ 5\na equ b+1\nout #a\nhlt").unwrap(),
+        vec![104, 6, 99]
+    );
+    let err = assemble("a equ 1\na: hlt").unwrap_err();
+    assert_eq!(err.message, "Duplicate symbol: a");
+}
+
+#[test]
+fn round_trips_through_disassembler() {
+    for path in &["data/02", "data/05", "data/07", "data/09"] {
+        let program = crate::Machine::from_file(path).state().to_vec();
+        let text: String = crate::disasm::disassemble(&program)
+            .iter()
+            .map(|line| format!("{}\n", line.text()))
+            .collect();
+        let assembled = assemble(&text).unwrap();
+        assert_eq!(assembled, program, "{}", path);
+        for line in crate::disasm::disassemble(&assembled) {
+            if let crate::disasm::Line::Instr { instr, words, .. } = line {
+                assert_eq!(crate::decode(words[0]), Some(instr));
+                assert_eq!(instr.encode(), words[0]);
+            }
+        }
+    }
+}
@@ -0,0 +1,314 @@
+//! Arbitrary-precision integers for `Arithmetic::Big`.
+//!
+//! Under that policy additions and multiplications never overflow: a cell
+//! whose value doesn't fit in an `i64` keeps its low 64 bits, and memory
+//! holds the exact `BigInt` alongside. Addresses, jump targets and the
+//! relative base must still fit in an `i64`.
+
+use std::cmp::Ordering;
+use std::convert::TryInto;
+use std::fmt;
+use std::ops::{Add, Mul, Neg};
+use std::str::FromStr;
+
+#[cfg(test)]
+use crate::{Arithmetic, Event, Machine, MachineError};
+
+/// A signed integer of unbounded size.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct BigInt {
+    negative: bool,
+    /// Magnitude in base 2^32, least significant limb first, with no
+    /// trailing zero limbs. Zero is empty and never negative.
+    mag: Vec<u32>,
+}
+
+impl BigInt {
+    fn from_parts(negative: bool, mut mag: Vec<u32>) -> Self {
+        while mag.last() == Some(&0) {
+            mag.pop();
+        }
+        let negative = negative && !mag.is_empty();
+        BigInt { negative, mag }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.mag.is_empty()
+    }
+
+    /// The value as an `i64`, if it fits.
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.mag.len() > 2 {
+            return None;
+        }
+        let mag = self
+            .mag
+            .iter()
+            .rev()
+            .fold(0u64, |acc, &limb| (acc << 32) | limb as u64);
+        if self.negative {
+            (-(mag as i128)).try_into().ok()
+        } else {
+            mag.try_into().ok()
+        }
+    }
+
+    /// The low 64 bits in two's complement, as wrapping `i64` arithmetic
+    /// would give.
+    pub fn wrapped(&self) -> i64 {
+        let low = self
+            .mag
+            .iter()
+            .take(2)
+            .rev()
+            .fold(0u64, |acc, &limb| (acc << 32) | limb as u64);
+        if self.negative {
+            low.wrapping_neg() as i64
+        } else {
+            low as i64
+        }
+    }
+
+    /// Divide the magnitude in place by a small divisor, returning the
+    /// remainder.
+    fn div_rem_small(mag: &mut Vec<u32>, divisor: u32) -> u32 {
+        let mut rem = 0u64;
+        for limb in mag.iter_mut().rev() {
+            let cur = (rem << 32) | *limb as u64;
+            *limb = (cur / divisor as u64) as u32;
+            rem = cur % divisor as u64;
+        }
+        while mag.last() == Some(&0) {
+            mag.pop();
+        }
+        rem as u32
+    }
+}
+
+fn cmp_mag(a: &[u32], b: &[u32]) -> Ordering {
+    a.len()
+        .cmp(&b.len())
+        .then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut out = Vec::with_capacity(a.len().max(b.len()) + 1);
+    let mut carry = 0u64;
+    for ix in 0..a.len().max(b.len()) {
+        let sum = *a.get(ix).unwrap_or(&0) as u64 + *b.get(ix).unwrap_or(&0) as u64 + carry;
+        out.push(sum as u32);
+        carry = sum >> 32;
+    }
+    out.push(carry as u32);
+    out
+}
+
+/// `a - b`, where `a >= b`.
+fn sub_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut out = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (ix, &limb) in a.iter().enumerate() {
+        let mut diff = limb as i64 - *b.get(ix).unwrap_or(&0) as i64 - borrow;
+        borrow = if diff < 0 {
+            diff += 1 << 32;
+            1
+        } else {
+            0
+        };
+        out.push(diff as u32);
+    }
+    out
+}
+
+fn mul_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut out = vec![0u32; a.len() + b.len()];
+    for (i, &x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+        for (j, &y) in b.iter().enumerate() {
+            let cur = out[i + j] as u64 + x as u64 * y as u64 + carry;
+            out[i + j] = cur as u32;
+            carry = cur >> 32;
+        }
+        out[i + b.len()] = carry as u32;
+    }
+    out
+}
+
+impl From<i64> for BigInt {
+    fn from(val: i64) -> Self {
+        let mag = val.unsigned_abs();
+        BigInt::from_parts(val < 0, vec![mag as u32, (mag >> 32) as u32])
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::from_parts(self.negative, add_mag(&self.mag, &other.mag));
+        }
+        match cmp_mag(&self.mag, &other.mag) {
+            Ordering::Less => BigInt::from_parts(other.negative, sub_mag(&other.mag, &self.mag)),
+            _ => BigInt::from_parts(self.negative, sub_mag(&self.mag, &other.mag)),
+        }
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+    fn mul(self, other: &BigInt) -> BigInt {
+        BigInt::from_parts(
+            self.negative != other.negative,
+            mul_mag(&self.mag, &other.mag),
+        )
+    }
+}
+
+impl Neg for BigInt {
+    type Output = BigInt;
+    fn neg(self) -> BigInt {
+        BigInt::from_parts(!self.negative, self.mag)
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_mag(&self.mag, &other.mag),
+            (true, true) => cmp_mag(&other.mag, &self.mag),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+        // Peel off nine decimal digits at a time.
+        let mut mag = self.mag.clone();
+        let mut chunks = Vec::new();
+        while !mag.is_empty() {
+            chunks.push(BigInt::div_rem_small(&mut mag, 1_000_000_000));
+        }
+        if self.negative {
+            write!(f, "-")?;
+        }
+        write!(f, "{}", chunks.pop().unwrap())?;
+        for chunk in chunks.iter().rev() {
+            write!(f, "{:09}", chunk)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for BigInt {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(format!("Bad integer: {:?}", s));
+        }
+        let mut mag: Vec<u32> = Vec::new();
+        for b in digits.bytes() {
+            let mut carry = (b - b'0') as u64;
+            for limb in mag.iter_mut() {
+                let cur = *limb as u64 * 10 + carry;
+                *limb = cur as u32;
+                carry = cur >> 32;
+            }
+            if carry > 0 {
+                mag.push(carry as u32);
+            }
+        }
+        Ok(BigInt::from_parts(negative, mag))
+    }
+}
+
+#[test]
+fn bigint_arithmetic() {
+    let big = |s: &str| s.parse::<BigInt>().unwrap();
+    let a = big("1125899906842624");
+    assert_eq!((&a * &a).to_string(), "1267650600228229401496703205376");
+    assert_eq!(
+        (&a * &-a.clone()).to_string(),
+        "-1267650600228229401496703205376"
+    );
+    assert_eq!((&big("-5") + &big("3")).to_string(), "-2");
+    assert_eq!((&big("5") + &big("-5")), BigInt::default());
+    assert_eq!(big("-9223372036854775808").to_i64(), Some(i64::MIN));
+    assert_eq!(big("9223372036854775808").to_i64(), None);
+    assert!(big("-10000000000000000000000") < big("-1"));
+    assert!(big("10000000000000000000000") > big("9999999999999999999999"));
+    assert_eq!(big("18446744073709551621").wrapped(), 5);
+    assert_eq!(big("-18446744073709551621").wrapped(), -5);
+    assert_eq!(big("-9223372036854775809").wrapped(), i64::MAX);
+}
+
+#[test]
+fn big_arithmetic_does_not_overflow() {
+    // Square the largest value day 9 prints, then output it.
+    let program = vec![
+        1002,
+        9,
+        1125899906842624,
+        9,
+        4,
+        9,
+        99,
+        0,
+        0,
+        1125899906842624,
+    ];
+    let square: BigInt = "1267650600228229401496703205376".parse().unwrap();
+    let mut machine = Machine::new(program).with_arithmetic(Arithmetic::Big);
+    assert_eq!(
+        machine.run_until_event(),
+        Ok(Event::Output(square.wrapped()))
+    );
+    assert_eq!(machine.exact_output(), Some(&square));
+    assert_eq!(machine.peek(9), 0);
+    assert_eq!(machine.peek_exact(9), square);
+
+    // A huge value can't be used as an address or an instruction.
+    let mut machine =
+        Machine::new(vec![1002, 6, 1 << 62, 6, 1105, 1, 4, 99]).with_arithmetic(Arithmetic::Big);
+    assert_eq!(
+        machine.run_until_event(),
+        Err(MachineError::Overflow { ip: 4, instr: 1105 })
+    );
+    machine.set_ip(6);
+    assert_eq!(
+        machine.run_until_event(),
+        Err(MachineError::BadOpcode { ip: 6, instr: 0 })
+    );
+}
+
+#[test]
+fn big_arithmetic_agrees_with_wrapping() {
+    for &(path, input) in &[("data/05", 5), ("data/09", 1)] {
+        let program = Machine::from_file(path).state().to_vec();
+        let mut machine = Machine::new(program.clone());
+        let mut big = Machine::new(program).with_arithmetic(Arithmetic::Big);
+        machine.provide_input(input);
+        big.provide_input(input);
+        loop {
+            let event = machine.run_until_event().unwrap();
+            assert_eq!(big.run_until_event().unwrap(), event);
+            if let Event::Halted(_) = event {
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,13 @@
+use advent2019::{cfg, Machine};
+
+fn main() {
+    let path = match std::env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: cfg <program>");
+            std::process::exit(1);
+        }
+    };
+    let program = Machine::from_file(&path).state().to_vec();
+    print!("{}", cfg::analyze(&program).to_dot(&program));
+}
@@ -1,3 +1,4 @@
+use advent2019::symbolic::{Goal, Solver};
 use advent2019::Machine;
 
 fn main() {
@@ -9,15 +10,15 @@ fn main() {
         println!("{}", res);
     }
 
-    for noun in 0..=99 {
-        for verb in 0..=99 {
-            let mut machine = Machine::init(machine.state().to_vec(), noun, verb);
-            let res = machine.run();
-            if res == 19690720 {
-                println!("noun {} verb {} outcome {}", noun, verb, 100 * noun + verb);
-                return;
-            }
-        }
+    // The result is linear in the noun and verb, so solve for them rather
+    // than trying every pair.
+    let solution = Solver::new(machine.state().to_vec())
+        .with_cell(1, 0..=99)
+        .with_cell(2, 0..=99)
+        .solve(Goal::Halted(19690720));
+    if let Some(solution) = solution {
+        let (noun, verb) = (solution.values[0], solution.values[1]);
+        println!("noun {} verb {} outcome {}", noun, verb, 100 * noun + verb);
     }
 }
 
@@ -7,10 +7,12 @@ enum Direction {
     Right,
 }
 
-fn parse(data: &str) -> (Vec<(Direction, i32)>, Vec<(Direction, i32)>) {
+type Route = Vec<(Direction, i32)>;
+
+fn parse(data: &str) -> (Route, Route) {
     fn parse_entry(entry: &str) -> (Direction, i32) {
         use Direction::*;
-        let dirn = match entry.chars().nth(0).unwrap() {
+        let dirn = match entry.chars().next().unwrap() {
             'U' => Up,
             'D' => Down,
             'R' => Right,
@@ -49,10 +51,10 @@ fn get_route(route: &[(Direction, i32)]) -> BTreeMap<(i32, i32), u32> {
 }
 
 fn get_min_dist(route1: &[(Direction, i32)], route2: &[(Direction, i32)]) -> (u32, u32) {
-    let route1 = get_route(&route1);
-    let route2 = get_route(&route2);
-    let mut min_manhat = std::u32::MAX;
-    let mut min_route = std::u32::MAX;
+    let route1 = get_route(route1);
+    let route2 = get_route(route2);
+    let mut min_manhat = u32::MAX;
+    let mut min_route = u32::MAX;
     for (posn, steps1) in route1.iter() {
         if let Some(steps2) = route2.get(posn) {
             let manhat = (posn.0.abs() + posn.1.abs()) as u32;
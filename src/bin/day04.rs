@@ -2,7 +2,7 @@ fn digits(mut v: u32) -> Vec<u32> {
     let mut digits = Vec::new();
     loop {
         digits.push(v % 10);
-        v = v / 10;
+        v /= 10;
         if v == 0 {
             break;
         }
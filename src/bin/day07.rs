@@ -1,8 +1,4 @@
-use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
-use std::thread;
-
-use itertools::Itertools;
-
+use advent2019::phases::PhaseSearch;
 use advent2019::Machine;
 
 fn main() {
@@ -13,58 +9,16 @@ fn main() {
     println!("{}", best);
 }
 
-fn run_chained_machines(data: &[i64], phases: (i64, i64, i64, i64, i64)) -> i64 {
-    let (tx1, rx1) = sync_channel(0);
-    let (tx2, rx2) = sync_channel(0);
-    let (tx3, rx3) = sync_channel(0);
-    let (tx4, rx4) = sync_channel(0);
-    let (tx5, rx5) = sync_channel(0);
-    let (tx6, rx6) = sync_channel(0);
-
-    let machines = vec![
-        Machine::new_with_io(data.to_vec(), takes_input(phases.0, rx1), send_output(tx2)),
-        Machine::new_with_io(data.to_vec(), takes_input(phases.1, rx2), send_output(tx3)),
-        Machine::new_with_io(data.to_vec(), takes_input(phases.2, rx3), send_output(tx4)),
-        Machine::new_with_io(data.to_vec(), takes_input(phases.3, rx4), send_output(tx5)),
-        Machine::new_with_io(data.to_vec(), takes_input(phases.4, rx5), send_output(tx6)),
-    ];
-
-    let _handles: Vec<_> = machines
-        .into_iter()
-        .map(|mut m| thread::spawn(move || m.run()))
-        .collect();
-    tx1.send(0).unwrap();
-    loop {
-        let out = rx6.recv().unwrap();
-        if let Err(_) = tx1.send(out) {
-            return out;
-        }
-    }
-}
-
 fn find_best_amplitude(data: &[i64], loopback: bool) -> i64 {
-    let range = if loopback { 5..=9 } else { 0..=4 };
-    range
-        .permutations(5)
-        .map(|combo| run_chained_machines(data, (combo[0], combo[1], combo[2], combo[3], combo[4])))
-        .max()
-        .unwrap()
-}
-
-fn takes_input(inp: i64, rx: Receiver<i64>) -> impl FnMut() -> i64 {
-    let mut has_fired = false;
-    move || {
-        if has_fired {
-            rx.recv().unwrap()
-        } else {
-            has_fired = true;
-            inp
-        }
-    }
-}
-
-fn send_output(tx: SyncSender<i64>) -> impl FnMut(i64) {
-    move |val| tx.send(val).unwrap()
+    let phases = if loopback { 5..=9 } else { 0..=4 };
+    let ranking = PhaseSearch::new(data.to_vec(), phases, 5)
+        .with_feedback(loopback)
+        .run()
+        .unwrap();
+    ranking
+        .best()
+        .expect("No phase setting produced a signal")
+        .signal
 }
 
 #[test]
@@ -88,10 +88,10 @@ fn main() {
     let img = read(25, 6);
     let (minix, _minval) = img
         .iter()
-        .map(|layer| layer_count_byte(&*layer, Pixel::Black))
+        .map(|layer| layer_count_byte(layer, Pixel::Black))
         .enumerate()
         .fold(
-            (std::usize::MAX, std::usize::MAX),
+            (usize::MAX, usize::MAX),
             |(accix, accval), (ix, val)| {
                 if val < accval {
                     (ix, val)
@@ -10,14 +10,7 @@ fn main() {
 #[test]
 fn example_9_1a() {
     let data = vec![109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99];
-    let (tx, rx) = std::sync::mpsc::channel();
-    let output = move |val| tx.send(val).unwrap();
-    {
-        let mut machine = Machine::new_with_io(data.clone(), || panic!(), output);
-        machine.run();
-    }
-
-    let output: Vec<_> = rx.iter().collect();
+    let output = Machine::new(data.clone()).run_with_inputs(&[]);
     assert_eq!(data, output);
 }
 
@@ -0,0 +1,383 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, BufRead, Write};
+
+use advent2019::disasm::format_instr;
+use advent2019::{decode, Event, Machine, MachineError};
+
+const HELP: &str = "\
+Commands:
+  s, step [n]          execute n instructions (default 1)
+  c, continue          run until a breakpoint, watchpoint, halt or error
+  b, break <addr>      break before executing the instruction at addr
+  b, break op <mnem>   break before any instruction with that mnemonic
+  w, watch <addr>      stop when the value at addr changes
+  d, delete            remove all breakpoints and watchpoints
+  i, info              list breakpoints and watchpoints
+  x, mem <addr> [n]    show n memory cells starting at addr (default 8)
+  set <addr> <val>     write val to addr
+  ip [val]             show or set the instruction pointer
+  rb [val]             show or set the relative base
+  sm, selfmod          list writes to instructions that had already run
+  h, help              show this message
+  q, quit              exit";
+
+/// Why execution handed control back to the user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Stop {
+    Stepped,
+    Breakpoint(usize),
+    OpBreak(&'static str),
+    Watch { addr: usize, old: i64, new: i64 },
+    Halted(i64),
+    Error(MachineError),
+    NoInput,
+}
+
+struct Debugger {
+    machine: Machine,
+    breakpoints: BTreeSet<usize>,
+    op_breaks: BTreeSet<String>,
+    watches: BTreeMap<usize, i64>,
+}
+
+impl Debugger {
+    fn new(program: Vec<i64>) -> Self {
+        Debugger {
+            machine: Machine::new(program).with_self_modification_tracking(),
+            breakpoints: BTreeSet::new(),
+            op_breaks: BTreeSet::new(),
+            watches: BTreeMap::new(),
+        }
+    }
+
+    /// The value at addr, or `None` if it is past the memory limit.
+    fn read(&self, addr: usize) -> Option<i64> {
+        if addr < self.machine.memory_limit() {
+            Some(self.machine.peek(addr))
+        } else {
+            None
+        }
+    }
+
+    /// Describe the instruction at ip, e.g. `ip 2 rb 0 | 1002 4 3 4 | mul 4, #3, 4`.
+    fn current(&self) -> String {
+        let ip = self.machine.ip();
+        let rb = self.machine.relative_base();
+        let word = match self.read(ip) {
+            Some(word) => word,
+            None => return format!("ip {} rb {} | <ip out of range>", ip, rb),
+        };
+        let args = decode(word).and_then(|instr| {
+            let args: Option<Vec<i64>> = (1..instr.width())
+                .map(|ix| self.read(ip.checked_add(ix)?))
+                .collect();
+            Some((instr, args?))
+        });
+        let text = match args {
+            Some((instr, args)) => {
+                let words = std::iter::once(word)
+                    .chain(args.iter().cloned())
+                    .map(|w| w.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                format!("{} | {}", words, format_instr(&instr, &args))
+            }
+            None => format!("{} | .data {}", word, word),
+        };
+        format!("ip {} rb {} | {}", ip, rb, text)
+    }
+
+    fn breaks_here(&self) -> Option<Stop> {
+        let ip = self.machine.ip();
+        if self.breakpoints.contains(&ip) {
+            return Some(Stop::Breakpoint(ip));
+        }
+        let instr = decode(self.read(ip)?)?;
+        if self.op_breaks.contains(instr.mnemonic()) {
+            return Some(Stop::OpBreak(instr.mnemonic()));
+        }
+        None
+    }
+
+    /// Execute up to `steps` instructions (or forever), stopping early at
+    /// breakpoints and watchpoints. Breakpoints at the starting ip are
+    /// ignored so that `continue` can move past them.
+    fn resume(
+        &mut self,
+        steps: Option<usize>,
+        input: &mut dyn FnMut() -> Option<i64>,
+        output: &mut dyn FnMut(i64),
+    ) -> Stop {
+        let mut count = 0;
+        loop {
+            if steps.map(|n| count >= n).unwrap_or(false) {
+                return Stop::Stepped;
+            }
+            if count > 0 {
+                if let Some(stop) = self.breaks_here() {
+                    return stop;
+                }
+            }
+            match self.machine.step_event() {
+                Ok(Some(Event::NeedInput)) => match input() {
+                    Some(val) => {
+                        self.machine.provide_input(val);
+                        continue;
+                    }
+                    None => return Stop::NoInput,
+                },
+                Ok(Some(Event::Output(val))) => output(val),
+                Ok(Some(Event::Halted(val))) => return Stop::Halted(val),
+                Ok(None) => {}
+                Err(e) => return Stop::Error(e),
+            }
+            count += 1;
+            for (&addr, old) in self.watches.iter_mut() {
+                let new = self.machine.peek(addr);
+                if new != *old {
+                    let stop = Stop::Watch {
+                        addr,
+                        old: *old,
+                        new,
+                    };
+                    *old = new;
+                    return stop;
+                }
+            }
+        }
+    }
+
+    fn address(&self, arg: Option<&str>) -> Result<usize, String> {
+        let arg = arg.ok_or("Missing address")?;
+        match arg.parse::<usize>() {
+            Ok(addr) if addr < self.machine.memory_limit() => Ok(addr),
+            _ => Err(format!("Bad address: {}", arg)),
+        }
+    }
+
+    /// Run one command line. Returns `None` when the user wants to quit.
+    fn command(
+        &mut self,
+        line: &str,
+        input: &mut dyn FnMut() -> Option<i64>,
+        output: &mut dyn FnMut(i64),
+    ) -> Option<Result<String, String>> {
+        let mut words = line.split_whitespace();
+        let cmd = match words.next() {
+            Some(cmd) => cmd,
+            None => return Some(Ok(String::new())),
+        };
+        let arg1 = words.next();
+        let arg2 = words.next();
+        let num = |arg: Option<&str>| -> Result<i64, String> {
+            let arg = arg.ok_or("Missing value")?;
+            arg.parse().map_err(|_| format!("Bad value: {}", arg))
+        };
+        let res = match cmd {
+            "q" | "quit" => return None,
+            "h" | "help" => Ok(HELP.to_string()),
+            "s" | "step" | "c" | "continue" => {
+                let steps = match (cmd, arg1) {
+                    ("c", _) | ("continue", _) => Ok(None),
+                    (_, None) => Ok(Some(1)),
+                    (_, Some(n)) => n.parse().map(Some).map_err(|_| format!("Bad count: {}", n)),
+                };
+                steps.map(|steps| {
+                    let stop = self.resume(steps, input, output);
+                    describe(&stop, &self.current())
+                })
+            }
+            "b" | "break" => match arg1 {
+                Some("op") => match arg2 {
+                    Some(mnem) => {
+                        self.op_breaks.insert(mnem.to_string());
+                        Ok(format!("Breakpoint on {}", mnem))
+                    }
+                    None => Err("Missing mnemonic".to_string()),
+                },
+                _ => self.address(arg1).map(|addr| {
+                    self.breakpoints.insert(addr);
+                    format!("Breakpoint at {}", addr)
+                }),
+            },
+            "w" | "watch" => self.address(arg1).map(|addr| {
+                self.watches.insert(addr, self.machine.peek(addr));
+                format!("Watching {}", addr)
+            }),
+            "d" | "delete" => {
+                self.breakpoints.clear();
+                self.op_breaks.clear();
+                self.watches.clear();
+                Ok("Deleted all breakpoints and watchpoints".to_string())
+            }
+            "i" | "info" => Ok(format!(
+                "breakpoints: {:?}\nop breakpoints: {:?}\nwatchpoints: {:?}",
+                self.breakpoints,
+                self.op_breaks,
+                self.watches.keys().collect::<Vec<_>>()
+            )),
+            "x" | "mem" => self.address(arg1).and_then(|addr| {
+                let len = match arg2 {
+                    Some(_) => num(arg2)?.max(0) as usize,
+                    None => 8,
+                };
+                let end = addr.saturating_add(len).min(self.machine.memory_limit());
+                Ok((addr..end)
+                    .map(|a| format!("{:>5}: {}", a, self.machine.peek(a)))
+                    .collect::<Vec<_>>()
+                    .join("\n"))
+            }),
+            "set" => self.address(arg1).and_then(|addr| {
+                let val = num(arg2)?;
+                self.machine.poke(addr, val);
+                Ok(format!("{:>5}: {}", addr, val))
+            }),
+            "ip" => match arg1 {
+                None => Ok(self.current()),
+                Some(_) => self.address(arg1).map(|addr| {
+                    self.machine.set_ip(addr);
+                    self.current()
+                }),
+            },
+            "rb" => match arg1 {
+                None => Ok(format!("rb {}", self.machine.relative_base())),
+                Some(_) => num(arg1).map(|rb| {
+                    self.machine.set_relative_base(rb);
+                    format!("rb {}", rb)
+                }),
+            },
+            "sm" | "selfmod" => {
+                let sites = self.machine.self_modifications();
+                if sites.is_empty() {
+                    Ok("No self-modifying writes".to_string())
+                } else {
+                    Ok(sites
+                        .iter()
+                        .map(|site| site.to_string())
+                        .collect::<Vec<_>>()
+                        .join("\n"))
+                }
+            }
+            other => Err(format!("Unknown command: {} (try 'help')", other)),
+        };
+        Some(res)
+    }
+}
+
+fn describe(stop: &Stop, current: &str) -> String {
+    let reason = match stop {
+        Stop::Stepped => String::new(),
+        Stop::Breakpoint(addr) => format!("Breakpoint at {}\n", addr),
+        Stop::OpBreak(mnem) => format!("Breakpoint on {}\n", mnem),
+        Stop::Watch { addr, old, new } => format!("Watch {}: {} -> {}\n", addr, old, new),
+        Stop::Halted(val) => format!("Halted with {}\n", val),
+        Stop::Error(e) => format!("Error: {}\n", e),
+        Stop::NoInput => "No input\n".to_string(),
+    };
+    format!("{}{}", reason, current)
+}
+
+fn prompt(text: &str) -> Option<String> {
+    print!("{}", text);
+    io::stdout().flush().unwrap();
+    let mut line = String::new();
+    match io::stdin().lock().read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line),
+    }
+}
+
+fn read_input() -> Option<i64> {
+    loop {
+        let line = prompt("Input > ")?;
+        match line.trim().parse() {
+            Ok(val) => return Some(val),
+            Err(_) => println!("Bad input"),
+        }
+    }
+}
+
+fn main() {
+    let path = match std::env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: debug <program>");
+            std::process::exit(1);
+        }
+    };
+    let program = Machine::from_file(&path).state().to_vec();
+    let mut debugger = Debugger::new(program);
+    println!("{}", debugger.current());
+    while let Some(line) = prompt("(debug) ") {
+        let res = debugger.command(&line, &mut read_input, &mut |v| println!("Output: {}", v));
+        match res {
+            None => break,
+            Some(Ok(text)) => {
+                if !text.is_empty() {
+                    println!("{}", text)
+                }
+            }
+            Some(Err(e)) => println!("{}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+fn run_commands(debugger: &mut Debugger, cmds: &[&str]) -> Vec<String> {
+    cmds.iter()
+        .map(
+            |cmd| match debugger.command(cmd, &mut || Some(5), &mut |_| ()) {
+                Some(Ok(text)) | Some(Err(text)) => text,
+                None => "quit".to_string(),
+            },
+        )
+        .collect()
+}
+
+#[test]
+fn breakpoints_and_stepping() {
+    // in 9; mul 9, #2, 9; out 9; hlt
+    let mut debugger = Debugger::new(vec![3, 9, 1002, 9, 2, 9, 4, 9, 99, 0]);
+    let out = run_commands(
+        &mut debugger,
+        &["break 6", "continue", "mem 9 1", "step", "continue"],
+    );
+    assert_eq!(out[1], "Breakpoint at 6\nip 6 rb 0 | 4 9 | out 9");
+    assert_eq!(out[2], "    9: 10");
+    assert_eq!(out[3], "ip 8 rb 0 | 99 | hlt");
+    assert!(out[4].starts_with("Halted with 3"));
+}
+
+#[test]
+fn watchpoints_and_op_breaks() {
+    let mut debugger = Debugger::new(vec![3, 9, 1002, 9, 2, 9, 4, 9, 99, 0]);
+    let out = run_commands(
+        &mut debugger,
+        &["watch 9", "c", "c", "d", "break op hlt", "c"],
+    );
+    assert_eq!(
+        out[1],
+        "Watch 9: 0 -> 5\nip 2 rb 0 | 1002 9 2 9 | mul 9, #2, 9"
+    );
+    assert_eq!(out[2], "Watch 9: 5 -> 10\nip 6 rb 0 | 4 9 | out 9");
+    assert_eq!(out[5], "Breakpoint on hlt\nip 8 rb 0 | 99 | hlt");
+}
+
+#[test]
+fn lists_self_modification() {
+    // add 1, #1, 1 bumps its own operand; out 1; hlt
+    let mut debugger = Debugger::new(vec![1001, 1, 1, 1, 4, 1, 99]);
+    let out = run_commands(&mut debugger, &["sm", "c", "selfmod"]);
+    assert_eq!(out[0], "No self-modifying writes");
+    assert_eq!(out[2], "ip 0 wrote 1: 1 -> 2");
+}
+
+#[test]
+fn reports_errors_past_the_memory_limit() {
+    // jt #1, #5000000000 jumps far past the end of memory
+    let mut debugger = Debugger::new(vec![1105, 1, 5_000_000_000]);
+    let out = run_commands(&mut debugger, &["break op add", "c", "ip"]);
+    assert!(out[1].starts_with("Error: "));
+    assert!(out[1].ends_with("ip 5000000000 rb 0 | <ip out of range>"));
+    assert_eq!(out[2], "ip 5000000000 rb 0 | <ip out of range>");
+}
@@ -0,0 +1,13 @@
+use advent2019::{disasm, Machine};
+
+fn main() {
+    let path = match std::env::args().nth(1) {
+        Some(path) => path,
+        None => {
+            eprintln!("Usage: disasm <program>");
+            std::process::exit(1);
+        }
+    };
+    let machine = Machine::from_file(&path);
+    print!("{}", disasm::listing(machine.state()));
+}
@@ -0,0 +1,33 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use advent2019::fuzz::Fuzzer;
+
+const USAGE: &str = "Usage: fuzz [cases] [seed]";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let number = |ix: usize| {
+        args.get(ix).map(|arg| match arg.parse() {
+            Ok(n) => n,
+            Err(_) => {
+                eprintln!("Bad number: {}\n{}", arg, USAGE);
+                std::process::exit(1);
+            }
+        })
+    };
+    let cases = number(0).unwrap_or(10_000);
+    let seed = number(1).unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(1)
+    });
+    println!("Running {} cases with seed {}", cases, seed);
+    match Fuzzer::new(seed).with_cases(cases as usize).run() {
+        Ok(()) => println!("All cases passed"),
+        Err(failure) => {
+            println!("{}", failure);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use std::fs;
+
+use advent2019::image::is_image;
+use advent2019::{parse_program, Machine, MachineSnapshot};
+
+const USAGE: &str = "\
+Usage: image <input> <output>
+
+Converts between text and binary images. A binary input is written as a
+comma-separated program if that is all it holds, and otherwise as snapshot
+text. A text input (a program, or snapshot text) is written as binary.";
+
+fn convert(input: &[u8]) -> Result<Vec<u8>, String> {
+    if is_image(input) {
+        let snapshot = MachineSnapshot::from_bytes(input).map_err(|e| e.to_string())?;
+        let program = snapshot.state().to_vec();
+        let text = if Machine::new(program.clone()).snapshot() == snapshot {
+            let words: Vec<_> = program.iter().map(|v| v.to_string()).collect();
+            format!("{}\n", words.join(","))
+        } else {
+            snapshot.to_string()
+        };
+        return Ok(text.into_bytes());
+    }
+    let text = std::str::from_utf8(input).map_err(|_| "Input is neither text nor an image")?;
+    let snapshot = if text.starts_with("ip ") {
+        text.parse::<MachineSnapshot>()?
+    } else {
+        let program = parse_program(text).map_err(|e| e.to_string())?;
+        Machine::new(program).snapshot()
+    };
+    snapshot.to_bytes().map_err(|e| e.to_string())
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() != 2 {
+        eprintln!("{}", USAGE);
+        std::process::exit(1);
+    }
+    let result = fs::read(&args[0])
+        .map_err(|e| format!("{}: {}", args[0], e))
+        .and_then(|input| convert(&input).map_err(|e| format!("{}: {}", args[0], e)))
+        .and_then(|output| fs::write(&args[1], output).map_err(|e| format!("{}: {}", args[1], e)));
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+#[test]
+fn converts_both_ways() {
+    let text = fs::read("data/05").unwrap();
+    let binary = convert(&text).unwrap();
+    assert!(is_image(&binary));
+    let back = convert(&binary).unwrap();
+    assert_eq!(
+        parse_program(std::str::from_utf8(&back).unwrap()),
+        parse_program(std::str::from_utf8(&text).unwrap())
+    );
+
+    let mut machine = Machine::new(vec![3, 5, 4, 5, 99, 0]);
+    machine.provide_input(7);
+    let snapshot = machine.snapshot().to_string();
+    let binary = convert(snapshot.as_bytes()).unwrap();
+    let big = format!("{}exact 5=36893488147419103232\n", snapshot);
+    assert_eq!(convert(&binary).unwrap(), snapshot.into_bytes());
+
+    // Images can't hold the exact value, so nothing is written.
+    assert!(convert(big.as_bytes()).unwrap_err().contains("too large"));
+}
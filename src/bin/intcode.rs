@@ -0,0 +1,330 @@
+use std::io::{self, BufRead};
+use std::time::Duration;
+
+use advent2019::adapters::{file_input, AsciiInput, AsciiOutput, Collector, Input};
+use advent2019::{load_program, Limits, Machine, Outcome, StopReason};
+
+const USAGE: &str = "\
+Usage: intcode [options] <program>
+
+Options:
+  --noun <n>            write n to address 1 before running
+  --verb <v>            write v to address 2 before running
+  --set <addr>=<val>    write val to addr before running (repeatable)
+  -i, --input <values>  comma-separated values to input first (repeatable)
+  --input-file <path>   input the values in a file first (repeatable)
+  -f, --format <fmt>    output as decimal (default), ascii or json
+  --ascii               same as --format ascii
+  --max-steps <n>       stop after n instructions
+  --timeout <secs>      stop after this many seconds
+  -h, --help            show this message
+
+Values from --input and --input-file are input in the order given. Once
+they run out, more are read from stdin: a number per line, or whole lines of
+text with --format ascii.
+
+Exit status: 0 halted, 1 bad usage or program, 2 machine error, 3 stopped by
+--max-steps or --timeout.";
+
+const EXIT_USAGE: i32 = 1;
+const EXIT_ERROR: i32 = 2;
+const EXIT_STOPPED: i32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Decimal,
+    Ascii,
+    Json,
+}
+
+/// A source of input values named on the command line.
+#[derive(Debug, Clone, PartialEq)]
+enum InputArg {
+    Value(i64),
+    File(String),
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Options {
+    path: String,
+    patches: Vec<(usize, i64)>,
+    inputs: Vec<InputArg>,
+    format: Option<Format>,
+    max_steps: Option<u64>,
+    timeout: Option<Duration>,
+    help: bool,
+}
+
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    fn num<T: std::str::FromStr>(what: &str, text: &str) -> Result<T, String> {
+        text.trim()
+            .parse()
+            .map_err(|_| format!("Bad {}: {}", what, text))
+    }
+
+    let mut options = Options::default();
+    let mut path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .map(|v| v.as_str())
+                .ok_or(format!("Missing value for {}", name))
+        };
+        match arg.as_str() {
+            "-h" | "--help" => options.help = true,
+            "--noun" => options.patches.push((1, num("noun", value(arg)?)?)),
+            "--verb" => options.patches.push((2, num("verb", value(arg)?)?)),
+            "--set" => {
+                let patch = value(arg)?;
+                let mut parts = patch.splitn(2, '=');
+                let addr = num("address", parts.next().unwrap())?;
+                let val = num(
+                    "value",
+                    parts.next().ok_or(format!("Bad patch: {}", patch))?,
+                )?;
+                options.patches.push((addr, val));
+            }
+            "-i" | "--input" => {
+                for val in value(arg)?.split(',').filter(|v| !v.trim().is_empty()) {
+                    options.inputs.push(InputArg::Value(num("input", val)?));
+                }
+            }
+            "--input-file" => options.inputs.push(InputArg::File(value(arg)?.to_string())),
+            "-f" | "--format" => {
+                options.format = Some(match value(arg)? {
+                    "decimal" => Format::Decimal,
+                    "ascii" => Format::Ascii,
+                    "json" => Format::Json,
+                    other => return Err(format!("Unknown format: {}", other)),
+                })
+            }
+            "--ascii" => options.format = Some(Format::Ascii),
+            "--max-steps" => options.max_steps = Some(num("step count", value(arg)?)?),
+            "--timeout" => {
+                let text = value(arg)?;
+                let secs: f64 = num("timeout", text)?;
+                let timeout = Duration::try_from_secs_f64(secs)
+                    .map_err(|_| format!("Bad timeout: {}", text))?;
+                options.timeout = Some(timeout);
+            }
+            _ if arg.starts_with('-') => return Err(format!("Unknown option: {}", arg)),
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument: {}", arg)),
+        }
+    }
+    if !options.help {
+        options.path = path.ok_or("Missing program path")?;
+    }
+    Ok(options)
+}
+
+fn json_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// How the run ended, as an exit status and, for JSON, the fields after
+/// `outputs`.
+fn summary(result: &Result<Outcome, advent2019::MachineError>) -> (i32, String) {
+    match result {
+        Ok(Outcome::Halted(val)) => (0, format!("\"status\":\"halted\",\"value\":{}", val)),
+        Ok(Outcome::Stopped { reason, ip, steps }) => {
+            let reason = match reason {
+                StopReason::StepLimit => "step_limit",
+                StopReason::Deadline => "timeout",
+                StopReason::Cancelled => "cancelled",
+            };
+            let text = format!(
+                "\"status\":\"stopped\",\"reason\":\"{}\",\"ip\":{},\"steps\":{}",
+                reason, ip, steps
+            );
+            (EXIT_STOPPED, text)
+        }
+        Err(e) => {
+            let text = format!(
+                "\"status\":\"error\",\"ip\":{},\"message\":{}",
+                e.ip(),
+                json_string(&e.to_string())
+            );
+            (EXIT_ERROR, text)
+        }
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(EXIT_USAGE);
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(e) => fail(&format!("{}\n\n{}", e, USAGE)),
+    };
+    if options.help {
+        println!("{}", USAGE);
+        return;
+    }
+
+    let program = match load_program(&options.path) {
+        Ok(program) => program,
+        Err(e) => fail(&format!("{}: {}", options.path, e)),
+    };
+    let format = options.format.unwrap_or(Format::Decimal);
+    let collected = Collector::new();
+    let mut machine = match format {
+        Format::Ascii => Machine::new_with_adapters(
+            program,
+            AsciiInput::new(io::BufReader::new(io::stdin())),
+            AsciiOutput::new(io::stdout()),
+        ),
+        Format::Decimal => {
+            Machine::new_with_adapters(program, read_number, |val: i64| println!("{}", val))
+        }
+        Format::Json => Machine::new_with_adapters(program, read_number, collected.sink()),
+    };
+    for &(addr, val) in &options.patches {
+        if addr >= machine.memory_limit() {
+            fail(&format!("Bad address: {}", addr));
+        }
+        machine.poke(addr, val);
+    }
+    for input in &options.inputs {
+        match input {
+            InputArg::Value(val) => machine.provide_input(*val),
+            InputArg::File(path) => match file_input(path) {
+                Ok(mut values) => {
+                    while let Some(val) = values.read() {
+                        machine.provide_input(val);
+                    }
+                }
+                Err(e) => fail(&format!("{}: {}", path, e)),
+            },
+        }
+    }
+
+    let mut limits = Limits::new();
+    if let Some(steps) = options.max_steps {
+        limits = limits.with_max_steps(steps);
+    }
+    if let Some(timeout) = options.timeout {
+        limits = limits.with_timeout(timeout);
+    }
+    let result = machine.run_with_limits(&limits);
+    // Flush any partial line of ASCII output before reporting.
+    drop(machine);
+
+    let (status, text) = summary(&result);
+    if format == Format::Json {
+        let outputs: Vec<_> = collected.values().iter().map(|v| v.to_string()).collect();
+        println!("{{\"outputs\":[{}],{}}}", outputs.join(","), text);
+    } else {
+        match result {
+            Ok(Outcome::Halted(_)) => {}
+            Ok(Outcome::Stopped { reason, ip, steps }) => {
+                eprintln!("{} at ip {} after {} instructions", reason, ip, steps)
+            }
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+    std::process::exit(status);
+}
+
+/// Read a number per line from stdin, without prompting, so that stdout
+/// holds nothing but output.
+fn read_number() -> Option<i64> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        match io::stdin().lock().read_line(&mut line) {
+            Ok(0) | Err(_) => return None,
+            Ok(_) => {}
+        }
+        if let Ok(val) = line.trim().parse() {
+            return Some(val);
+        }
+        eprintln!("Bad input: {}", line.trim());
+    }
+}
+
+#[cfg(test)]
+fn args(text: &str) -> Vec<String> {
+    text.split_whitespace().map(String::from).collect()
+}
+
+#[test]
+fn parses_options() {
+    let options = parse_args(&args(
+        "--noun 12 --verb 2 --set 5=-1 -i 1,2 --input-file in --input 3 -f json --max-steps 100 \
+         --timeout 1.5 prog",
+    ))
+    .unwrap();
+    assert_eq!(options.path, "prog");
+    assert_eq!(options.patches, vec![(1, 12), (2, 2), (5, -1)]);
+    assert_eq!(
+        options.inputs,
+        vec![
+            InputArg::Value(1),
+            InputArg::Value(2),
+            InputArg::File("in".to_string()),
+            InputArg::Value(3),
+        ]
+    );
+    assert_eq!(options.format, Some(Format::Json));
+    assert_eq!(options.max_steps, Some(100));
+    assert_eq!(options.timeout, Some(Duration::from_millis(1500)));
+}
+
+#[test]
+fn rejects_bad_options() {
+    assert_eq!(
+        parse_args(&args("--noun x prog")),
+        Err("Bad noun: x".to_string())
+    );
+    assert_eq!(
+        parse_args(&args("-f xml prog")),
+        Err("Unknown format: xml".to_string())
+    );
+    assert_eq!(
+        parse_args(&args("--timeout -1 prog")),
+        Err("Bad timeout: -1".to_string())
+    );
+    assert_eq!(
+        parse_args(&args("--timeout 1e30 prog")),
+        Err("Bad timeout: 1e30".to_string())
+    );
+    assert_eq!(
+        parse_args(&args("--max-steps")),
+        Err("Missing value for --max-steps".to_string())
+    );
+    assert_eq!(
+        parse_args(&args("--ascii")),
+        Err("Missing program path".to_string())
+    );
+}
+
+#[test]
+fn json_summaries() {
+    let (status, text) = summary(&Ok(Outcome::Stopped {
+        reason: StopReason::StepLimit,
+        ip: 4,
+        steps: 10,
+    }));
+    assert_eq!(status, EXIT_STOPPED);
+    assert_eq!(
+        text,
+        "\"status\":\"stopped\",\"reason\":\"step_limit\",\"ip\":4,\"steps\":10"
+    );
+    assert_eq!(json_string("a\"b\n"), "\"a\\\"b\\u000a\"");
+}
@@ -0,0 +1,248 @@
+//! Static control-flow graphs.
+//!
+//! Instructions are found as `disasm` finds them: everything reachable from
+//! address 0 by falling through or taking jumps with immediate targets, plus
+//! a linear sweep of the rest. Everything else is data. Blocks not reachable
+//! from 0 are kept, since programs like day 5's patch their own code or jump
+//! through memory, neither of which can be followed without running them.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use crate::disasm::{disassemble, flow, format_instr, reachable, Line};
+use crate::{Instr, OpNone};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EdgeKind {
+    /// On to the next instruction.
+    Fall,
+    /// A jump taken to an immediate target.
+    Jump,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Edge {
+    /// The start of the block control passes to.
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+/// A run of instructions only ever entered at the top and left at the bottom.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    pub start: usize,
+    /// One past the last word of the last instruction.
+    pub end: usize,
+    /// The address and decoded form of each instruction.
+    pub instrs: Vec<(usize, Instr)>,
+    pub successors: Vec<Edge>,
+    /// The block ends with a jump whose target is only known at run time.
+    pub indirect: bool,
+    /// Whether the block is statically reachable from address 0.
+    pub reachable: bool,
+}
+
+impl Block {
+    /// Whether the block ends the program, i.e. ends in `hlt`.
+    pub fn is_exit(&self) -> bool {
+        matches!(self.instrs.last(), Some((_, Instr::NoneArg(OpNone::Exit))))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cfg {
+    /// Basic blocks in address order.
+    pub blocks: Vec<Block>,
+    /// Ranges of addresses (start inclusive, end exclusive) holding data.
+    pub data: Vec<(usize, usize)>,
+}
+
+pub fn analyze(program: &[i64]) -> Cfg {
+    let reached = reachable(program);
+    let mut instrs = BTreeMap::new();
+    let mut data: Vec<(usize, usize)> = Vec::new();
+    for line in disassemble(program) {
+        match line {
+            Line::Instr { addr, instr, .. } => {
+                instrs.insert(addr, instr);
+            }
+            Line::Data { addr, .. } => match data.last_mut() {
+                Some((_, end)) if *end == addr => *end += 1,
+                _ => data.push((addr, addr + 1)),
+            },
+        }
+    }
+
+    // A block starts at every jump target and after every instruction that
+    // can jump or stop; anything else carries on into the next instruction.
+    let mut leaders = BTreeSet::new();
+    for (&addr, instr) in &instrs {
+        let flow = flow(program, addr, instr);
+        leaders.extend(flow.jump);
+        if flow.jump.is_some() || flow.indirect || flow.fall != Some(addr + instr.width()) {
+            leaders.insert(addr + instr.width());
+        }
+    }
+
+    let mut blocks: Vec<Block> = Vec::new();
+    for (&addr, &instr) in &instrs {
+        let continues = match blocks.last() {
+            Some(block) => block.end == addr && !leaders.contains(&addr),
+            None => false,
+        };
+        if !continues {
+            blocks.push(Block {
+                start: addr,
+                end: addr,
+                instrs: vec![],
+                successors: vec![],
+                indirect: false,
+                reachable: reached.contains(&addr),
+            });
+        }
+        let block = blocks.last_mut().unwrap();
+        block.instrs.push((addr, instr));
+        block.end = addr + instr.width();
+    }
+    for block in blocks.iter_mut() {
+        let &(addr, instr) = block.instrs.last().unwrap();
+        let flow = flow(program, addr, &instr);
+        let edges = [(flow.fall, EdgeKind::Fall), (flow.jump, EdgeKind::Jump)];
+        for &(to, kind) in edges.iter() {
+            if let Some(to) = to.filter(|to| instrs.contains_key(to)) {
+                block.successors.push(Edge { to, kind });
+            }
+        }
+        block.indirect = flow.indirect;
+    }
+
+    Cfg { blocks, data }
+}
+
+impl Cfg {
+    /// The block starting at `addr`.
+    pub fn block(&self, addr: usize) -> Option<&Block> {
+        self.blocks
+            .binary_search_by_key(&addr, |b| b.start)
+            .ok()
+            .map(|ix| &self.blocks[ix])
+    }
+
+    /// Render as a Graphviz digraph, e.g. for `dot -Tsvg`.
+    pub fn to_dot(&self, program: &[i64]) -> String {
+        let mut dot = String::new();
+        writeln!(dot, "digraph cfg {{").unwrap();
+        writeln!(dot, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+        for block in &self.blocks {
+            let mut label = String::new();
+            for &(addr, instr) in &block.instrs {
+                let args = &program[addr + 1..addr + instr.width()];
+                write!(label, "{:>5}: {}\\l", addr, format_instr(&instr, args)).unwrap();
+            }
+            let mut style = String::new();
+            if block.is_exit() {
+                style.push_str(", peripheries=2");
+            }
+            if !block.reachable {
+                style.push_str(", style=dashed, color=gray");
+            }
+            writeln!(dot, "    b{} [label=\"{}\"{}];", block.start, label, style).unwrap();
+            for edge in &block.successors {
+                let style = match edge.kind {
+                    EdgeKind::Fall => "style=dashed",
+                    EdgeKind::Jump => "label=\"jump\"",
+                };
+                writeln!(dot, "    b{} -> b{} [{}];", block.start, edge.to, style).unwrap();
+            }
+            if block.indirect {
+                writeln!(dot, "    b{} -> indirect [label=\"jump\"];", block.start).unwrap();
+            }
+        }
+        if self.blocks.iter().any(|b| b.indirect) {
+            writeln!(dot, "    indirect [shape=ellipse, label=\"?\"];").unwrap();
+        }
+        if !self.data.is_empty() {
+            let ranges: Vec<_> = self
+                .data
+                .iter()
+                .map(|&(start, end)| match end - start {
+                    1 => format!("{}", start),
+                    _ => format!("{}..{}", start, end - 1),
+                })
+                .collect();
+            writeln!(
+                dot,
+                "    data [shape=note, label=\"data: {}\"];",
+                ranges.join(", ")
+            )
+            .unwrap();
+        }
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+}
+
+#[test]
+fn splits_blocks_at_jumps() {
+    let program = crate::asm::assemble(
+        "
+                in n
+        loop:   jf n, #done
+                out n
+                add n, #-1, n
+                jt #1, #loop
+        done:   hlt
+        n:      db 0
+        ",
+    )
+    .unwrap();
+    let cfg = analyze(&program);
+    let starts: Vec<_> = cfg.blocks.iter().map(|b| b.start).collect();
+    assert_eq!(starts, vec![0, 2, 5, 14]);
+    assert_eq!(
+        cfg.block(2).unwrap().successors,
+        vec![
+            Edge {
+                to: 5,
+                kind: EdgeKind::Fall
+            },
+            Edge {
+                to: 14,
+                kind: EdgeKind::Jump
+            }
+        ]
+    );
+    assert_eq!(
+        cfg.block(5).unwrap().successors,
+        vec![Edge {
+            to: 2,
+            kind: EdgeKind::Jump
+        }]
+    );
+    assert!(cfg.block(14).unwrap().is_exit());
+    assert_eq!(cfg.data, vec![(15, 16)]);
+
+    let dot = cfg.to_dot(&program);
+    assert!(dot.starts_with("digraph cfg {"));
+    assert!(dot.contains("b5 -> b2 [label=\"jump\"];"));
+    assert!(dot.contains("data [shape=note, label=\"data: 15\"];"));
+}
+
+#[test]
+fn day05_graph() {
+    let program = crate::Machine::from_file("data/05").state().to_vec();
+    let cfg = analyze(&program);
+    assert_eq!(cfg.blocks[0].start, 0);
+    assert!(cfg.blocks.iter().any(|b| b.is_exit()));
+    // Day 5 patches the word at 6 into an instruction before running it,
+    // so statically only the first block is reachable.
+    assert_eq!(cfg.data[0], (6, 7));
+    assert!(cfg.blocks[0].reachable);
+    assert!(!cfg.blocks[1].reachable);
+    // Every edge leads to the start of a block.
+    for block in &cfg.blocks {
+        for edge in &block.successors {
+            assert!(cfg.block(edge.to).is_some(), "{:?}", edge);
+        }
+    }
+}
@@ -0,0 +1,547 @@
+//! Ahead-of-time translation of Intcode programs into Rust.
+//!
+//! Every instruction `disasm` finds becomes an arm of a `match` on `ip`,
+//! with its modes and operands baked in. The generated module exposes
+//!
+//! ```text
+//! pub static PROGRAM: [i64; N];
+//! pub fn run(machine: &mut Machine) -> Result<Event, MachineError>;
+//! ```
+//!
+//! where `run` behaves like `Machine::run_until_event`, keeping its state in
+//! the machine so the two can be mixed freely. Anything the compiled code
+//! doesn't cover is handed to the interpreter one instruction at a time:
+//! addresses no instruction was compiled for, faults and overflow (so errors
+//! and the machine's `Arithmetic` are exactly the interpreter's), and
+//! instructions whose words no longer match the program. Words are only
+//! compared once something may have written over compiled code. A machine
+//! using `Arithmetic::Big` runs entirely in the interpreter, since the
+//! compiled code only knows `i64` cells.
+//!
+//! From a build script:
+//!
+//! ```no_run
+//! use std::path::Path;
+//!
+//! let out = std::env::var("OUT_DIR").unwrap();
+//! advent2019::compile::Compiler::new()
+//!     .compile_file("data/09", Path::new(&out).join("day09.rs"))
+//!     .unwrap();
+//! println!("cargo:rerun-if-changed=data/09");
+//! ```
+//!
+//! and then `mod day09 { include!(concat!(env!("OUT_DIR"), "/day09.rs")); }`.
+
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use crate::disasm::{disassemble, format_instr, Line};
+use crate::{load_program, Instr, LoadError, Mode, OpNone, OpOne, OpThree, OpTwo};
+
+/// Emitted ahead of the instruction arms. `CRATE` is replaced with the path
+/// of this crate as seen from the generated code.
+const PRELUDE: &str = "\
+use CRATE::{Arithmetic, Event, Machine, MachineError};
+
+#[allow(dead_code)]
+enum Next {
+    Go(usize),
+    Output(usize, i64),
+    NeedInput,
+    Halt,
+}
+
+#[allow(dead_code)]
+fn addr(m: &Machine, a: i64) -> Option<usize> {
+    if a >= 0 && (a as usize) < m.memory_limit() {
+        Some(a as usize)
+    } else {
+        None
+    }
+}
+
+#[allow(dead_code)]
+fn load(m: &Machine, a: i64) -> Option<i64> {
+    addr(m, a).map(|a| m.peek(a))
+}
+
+#[allow(dead_code)]
+fn store(m: &mut Machine, a: usize, val: i64, dirty: &mut bool) {
+    if a < CODE.len() && CODE[a] {
+        *dirty = true;
+    }
+    m.poke(a, val);
+}
+
+fn unchanged(m: &Machine, at: usize, width: usize) -> bool {
+    (at..at + width).all(|a| load(m, a as i64) == Some(PROGRAM[a]))
+}
+
+/// Run until the machine wants input it has not been given, produces an
+/// output, or halts.
+pub fn run(m: &mut Machine) -> Result<Event, MachineError> {
+    if m.arithmetic() == Arithmetic::Big {
+        return m.run_until_event();
+    }
+    let mut ip = m.ip();
+    let mut rb = m.relative_base();
+    let mut dirty = (0..PROGRAM.len()).any(|a| CODE[a] && !unchanged(m, a, 1));
+    loop {
+        match step(m, ip, &mut rb, &mut dirty) {
+            Some(Next::Go(next)) => ip = next,
+            Some(Next::Output(next, val)) => {
+                m.set_ip(next);
+                m.set_relative_base(rb);
+                return Ok(Event::Output(val));
+            }
+            Some(Next::NeedInput) => {
+                m.set_ip(ip);
+                m.set_relative_base(rb);
+                return Ok(Event::NeedInput);
+            }
+            Some(Next::Halt) => {
+                m.set_ip(ip);
+                m.set_relative_base(rb);
+                return match load(m, 0) {
+                    Some(val) => Ok(Event::Halted(val)),
+                    // Let the interpreter report the fault.
+                    None => m.run_until_event(),
+                };
+            }
+            None => {
+                m.set_ip(ip);
+                m.set_relative_base(rb);
+                let event = m.step_event()?;
+                // The interpreter may have written anywhere.
+                dirty = true;
+                ip = m.ip();
+                rb = m.relative_base();
+                if let Some(event) = event {
+                    return Ok(event);
+                }
+            }
+        }
+    }
+}
+";
+
+#[derive(Debug, Clone)]
+pub struct Compiler {
+    crate_path: String,
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Compiler {
+            crate_path: "advent2019".to_string(),
+        }
+    }
+}
+
+/// Compile with the default settings; see `Compiler::compile`.
+pub fn compile(program: &[i64]) -> String {
+    Compiler::new().compile(program)
+}
+
+/// An operand as a Rust expression, with `?` falling back to the
+/// interpreter on a bad address or overflow.
+fn operand(mode: Mode, arg: i64) -> String {
+    match mode {
+        Mode::Immediate => format!("({}i64)", arg),
+        Mode::Position => format!("load(m, {})?", arg),
+        Mode::Relative => format!("load(m, rb.checked_add({})?)?", arg),
+    }
+}
+
+/// The address an operand writes to, as a `usize` expression.
+fn target(mode: Mode, arg: i64) -> String {
+    match mode {
+        Mode::Relative => format!("addr(m, rb.checked_add({})?)?", arg),
+        _ => format!("addr(m, {})?", arg),
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How generated code names this crate, e.g. `crate` when it is
+    /// included in this crate itself. Defaults to `advent2019`.
+    pub fn with_crate_path(mut self, path: &str) -> Self {
+        self.crate_path = path.to_string();
+        self
+    }
+
+    /// Translate `program` into the source of a Rust module.
+    pub fn compile(&self, program: &[i64]) -> String {
+        let mut code = vec![false; program.len()];
+        let mut arms = String::new();
+        for line in disassemble(program) {
+            if let Line::Instr { addr, instr, words } = line {
+                for cell in &mut code[addr..addr + words.len()] {
+                    *cell = true;
+                }
+                self.arm(&mut arms, addr, &instr, &words[1..]);
+            }
+        }
+
+        let mut src = String::new();
+        writeln!(
+            src,
+            "// Generated by advent2019::compile from a {}-word program. Do not edit.\n",
+            program.len()
+        )
+        .unwrap();
+        src.push_str(&PRELUDE.replace("CRATE", &self.crate_path));
+        writeln!(src, "\npub static PROGRAM: [i64; {}] = [", program.len()).unwrap();
+        for chunk in program.chunks(8) {
+            let words: Vec<_> = chunk.iter().map(|w| w.to_string()).collect();
+            writeln!(src, "    {},", words.join(", ")).unwrap();
+        }
+        writeln!(src, "];\n").unwrap();
+        writeln!(src, "/// Which words belong to compiled instructions.").unwrap();
+        writeln!(src, "static CODE: [bool; {}] = [", program.len()).unwrap();
+        for chunk in code.chunks(8) {
+            let flags: Vec<_> = chunk.iter().map(|c| c.to_string()).collect();
+            writeln!(src, "    {},", flags.join(", ")).unwrap();
+        }
+        writeln!(src, "];\n").unwrap();
+        writeln!(
+            src,
+            "#[allow(unused_parens, unused_variables, clippy::all)]"
+        )
+        .unwrap();
+        writeln!(
+            src,
+            "fn step(m: &mut Machine, ip: usize, rb: &mut i64, dirty: &mut bool) -> Option<Next> {{"
+        )
+        .unwrap();
+        writeln!(src, "    match ip {{").unwrap();
+        src.push_str(&arms);
+        writeln!(src, "        _ => None,").unwrap();
+        writeln!(src, "    }}").unwrap();
+        writeln!(src, "}}").unwrap();
+        src
+    }
+
+    /// Read a program and write the compiled module to `dest`, leaving the
+    /// file untouched if it wouldn't change so that builds stay fresh.
+    pub fn compile_file(
+        &self,
+        src: impl AsRef<Path>,
+        dest: impl AsRef<Path>,
+    ) -> Result<(), LoadError> {
+        let module = self.compile(&load_program(src)?);
+        if fs::read_to_string(&dest).ok().as_deref() != Some(module.as_str()) {
+            fs::write(dest, module)?;
+        }
+        Ok(())
+    }
+
+    fn arm(&self, out: &mut String, addr: usize, instr: &Instr, args: &[i64]) {
+        let next = addr + instr.width();
+        let modes = instr.modes();
+        let val = |ix: usize| operand(modes[ix], args[ix]);
+        let mut body = Vec::new();
+        match *instr {
+            Instr::NoneArg(OpNone::Exit) => body.push("Some(Next::Halt)".to_string()),
+            Instr::OneArg(OpOne::Input, _) => {
+                body.push(format!("let dst = {};", target(modes[0], args[0])));
+                body.push("let val = match m.take_input() {".to_string());
+                body.push("    Some(val) => val,".to_string());
+                body.push("    None => return Some(Next::NeedInput),".to_string());
+                body.push("};".to_string());
+                body.push("store(m, dst, val, dirty);".to_string());
+                body.push(format!("Some(Next::Go({}))", next));
+            }
+            Instr::OneArg(OpOne::Output, _) => {
+                body.push(format!("Some(Next::Output({}, {}))", next, val(0)));
+            }
+            Instr::OneArg(OpOne::AdjustRelativeBase, _) => {
+                body.push(format!("*rb = rb.checked_add({})?;", val(0)));
+                body.push(format!("Some(Next::Go({}))", next));
+            }
+            Instr::TwoArg(op, _) => {
+                let test = match op {
+                    OpTwo::JumpIfTrue => "!=",
+                    OpTwo::JumpIfFalse => "==",
+                };
+                // Both operands are read whether or not the jump is taken,
+                // as in the interpreter.
+                body.push(format!("let (cond, to) = ({}, {});", val(0), val(1)));
+                body.push(format!("if cond {} 0 {{", test));
+                body.push("    if to < 0 {".to_string());
+                body.push("        return None;".to_string());
+                body.push("    }".to_string());
+                body.push("    Some(Next::Go(to as usize))".to_string());
+                body.push("} else {".to_string());
+                body.push(format!("    Some(Next::Go({}))", next));
+                body.push("}".to_string());
+            }
+            Instr::ThreeArg(op, _) => {
+                body.push(format!("let (a, b) = ({}, {});", val(0), val(1)));
+                let result = match op {
+                    OpThree::Add => "a.checked_add(b)?",
+                    OpThree::Mul => "a.checked_mul(b)?",
+                    OpThree::LessThan => "(a < b) as i64",
+                    OpThree::Equals => "(a == b) as i64",
+                };
+                body.push(format!("let val = {};", result));
+                body.push(format!("let dst = {};", target(modes[2], args[2])));
+                body.push("store(m, dst, val, dirty);".to_string());
+                body.push(format!("Some(Next::Go({}))", next));
+            }
+        }
+
+        writeln!(out, "        // {}", format_instr(instr, args)).unwrap();
+        writeln!(out, "        {} => {{", addr).unwrap();
+        writeln!(
+            out,
+            "            if *dirty && !unchanged(m, {}, {}) {{",
+            addr,
+            instr.width()
+        )
+        .unwrap();
+        writeln!(out, "                return None;").unwrap();
+        writeln!(out, "            }}").unwrap();
+        for line in body {
+            writeln!(out, "            {}", line).unwrap();
+        }
+        writeln!(out, "        }}").unwrap();
+    }
+}
+
+#[test]
+fn specialises_arms_by_mode() {
+    let src = compile(&[1002, 4, 3, 4, 33]);
+    assert!(src.starts_with("// Generated by advent2019::compile from a 5-word program."));
+    assert!(src.contains("use advent2019::{Arithmetic, Event, Machine, MachineError};"));
+    assert!(src.contains(
+        "        // mul 4, #3, 4
+        0 => {
+            if *dirty && !unchanged(m, 0, 4) {
+                return None;
+            }
+            let (a, b) = (load(m, 4)?, (3i64));
+            let val = a.checked_mul(b)?;
+            let dst = addr(m, 4)?;
+            store(m, dst, val, dirty);
+            Some(Next::Go(4))
+        }
+"
+    ));
+    assert!(src.contains("static CODE: [bool; 5] = [\n    true, true, true, true, false,\n];"));
+    let src = Compiler::new()
+        .with_crate_path("crate")
+        .compile(&[204, -1, 99]);
+    assert!(src.contains("use crate::{Arithmetic, Event"));
+    assert!(src.contains("Some(Next::Output(2, load(m, rb.checked_add(-1)?)?))"));
+}
+
+/// Counts down through the relative base, patches an instruction it then
+/// runs, and overflows on large inputs.
+#[cfg(test)]
+const SAMPLE: &str = "
+        in n
+        mul n, #4, big
+        lt #0, big, pos
+        out pos
+        arb #count
+loop:   out @0
+        add @0, #-1, @0
+        jt @0, #loop
+        add #1, patch+1, patch+1
+patch:  out #7
+        hlt
+n:      db 0
+big:    db 0
+pos:    db 0
+count:  db 3
+";
+
+/// `data/compiled/sample.rs` is `SAMPLE` compiled, so that plain
+/// `cargo test` runs generated code. A stale copy is rewritten.
+#[test]
+fn sample_is_up_to_date() {
+    let program = crate::asm::assemble(SAMPLE).unwrap();
+    let src = Compiler::new().with_crate_path("crate").compile(&program);
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("data/compiled/sample.rs");
+    if fs::read_to_string(&path).ok().as_deref() != Some(src.as_str()) {
+        fs::write(&path, src).unwrap();
+        panic!(
+            "{} was out of date and has been regenerated",
+            path.display()
+        );
+    }
+}
+
+#[cfg(test)]
+mod sample {
+    include!("../data/compiled/sample.rs");
+}
+
+#[test]
+fn compiled_sample_matches_interpreter() {
+    use crate::{Arithmetic, Event, Machine, MachineError};
+
+    fn run_all(
+        machine: &mut Machine,
+        run: fn(&mut Machine) -> Result<Event, MachineError>,
+    ) -> (Vec<i64>, Result<Event, MachineError>) {
+        let mut outputs = vec![];
+        loop {
+            match run(machine) {
+                Ok(Event::Output(val)) => outputs.push(val),
+                other => return (outputs, other),
+            }
+        }
+    }
+
+    let arithmetics = [Arithmetic::Wrapping, Arithmetic::Checked, Arithmetic::Big];
+    for &arithmetic in &arithmetics {
+        for &input in &[5, -5, 1 << 62] {
+            let setup = || {
+                let mut machine =
+                    Machine::new(sample::PROGRAM.to_vec()).with_arithmetic(arithmetic);
+                machine.provide_input(input);
+                machine
+            };
+            let mut interpreted = setup();
+            let expected = run_all(&mut interpreted, Machine::run_until_event);
+            let mut compiled = setup();
+            let actual = run_all(&mut compiled, sample::run);
+            assert_eq!(actual, expected, "{:?} {}", arithmetic, input);
+            assert_eq!(compiled.state(), interpreted.state());
+            assert_eq!(compiled.ip(), interpreted.ip());
+        }
+    }
+
+    // Only exact arithmetic sees that 4 << 62 is positive.
+    let mut machine = Machine::new(sample::PROGRAM.to_vec()).with_arithmetic(Arithmetic::Big);
+    machine.provide_input(1 << 62);
+    assert_eq!(
+        run_all(&mut machine, sample::run),
+        (vec![1, 3, 2, 1, 8], Ok(Event::Halted(3)))
+    );
+}
+
+/// Build a scratch crate whose build script compiles the programs in
+/// `data/`, and check each runs exactly as it does in the interpreter.
+#[test]
+#[ignore = "runs a nested cargo build; use `cargo test -- --ignored`"]
+fn compiled_programs_match_interpreter() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let dir = root.join("target").join("aot-check");
+    fs::create_dir_all(dir.join("src")).unwrap();
+    let manifest = format!(
+        "[package]
+name = \"aot-check\"
+version = \"0.0.0\"
+edition = \"2018\"
+
+[dependencies]
+advent2019 = {{ path = {root:?} }}
+
+[build-dependencies]
+advent2019 = {{ path = {root:?} }}
+
+[workspace]
+",
+        root = root
+    );
+    let build = format!(
+        "fn main() {{
+    let out = std::path::PathBuf::from(std::env::var(\"OUT_DIR\").unwrap());
+    for day in &[\"02\", \"05\", \"07\", \"09\"] {{
+        let src = std::path::Path::new({data:?}).join(day);
+        println!(\"cargo:rerun-if-changed={{}}\", src.display());
+        advent2019::compile::Compiler::new()
+            .compile_file(&src, out.join(format!(\"day{{}}.rs\", day)))
+            .unwrap();
+    }}
+}}
+",
+        data = root.join("data")
+    );
+    let main = r#"
+use advent2019::{Event, Machine, MachineError};
+
+mod day02 { include!(concat!(env!("OUT_DIR"), "/day02.rs")); }
+mod day05 { include!(concat!(env!("OUT_DIR"), "/day05.rs")); }
+mod day07 { include!(concat!(env!("OUT_DIR"), "/day07.rs")); }
+mod day09 { include!(concat!(env!("OUT_DIR"), "/day09.rs")); }
+
+type Run = fn(&mut Machine) -> Result<Event, MachineError>;
+
+fn check(name: &str, program: &[i64], run: Run, patches: &[(usize, i64)], inputs: &[i64]) {
+    let setup = || {
+        let mut machine = Machine::new(program.to_vec());
+        for &(addr, val) in patches {
+            machine.poke(addr, val);
+        }
+        for &val in inputs {
+            machine.provide_input(val);
+        }
+        machine
+    };
+    let run_all = |machine: &mut Machine, run: &dyn Fn(&mut Machine) -> Result<Event, MachineError>| {
+        let mut outputs = vec![];
+        loop {
+            match run(machine) {
+                Ok(Event::Output(val)) => outputs.push(val),
+                other => return (outputs, other),
+            }
+        }
+    };
+    let mut interpreted = setup();
+    let expected = run_all(&mut interpreted, &|m| m.run_until_event());
+    let mut compiled = setup();
+    let actual = run_all(&mut compiled, &|m| run(m));
+    assert_eq!(actual, expected, "{} {:?} {:?}", name, patches, inputs);
+    assert_eq!(compiled.state(), interpreted.state(), "{}", name);
+    assert_eq!(compiled.ip(), interpreted.ip(), "{}", name);
+    println!("{} {:?} {:?}: {:?}", name, patches, inputs, actual);
+}
+
+fn main() {
+    check("02", &day02::PROGRAM, day02::run, &[], &[]);
+    check("02", &day02::PROGRAM, day02::run, &[(1, 12), (2, 2)], &[]);
+    check("02", &day02::PROGRAM, day02::run, &[(1, 3), (2, 200)], &[]);
+    for &input in &[1, 5, 8] {
+        check("05", &day05::PROGRAM, day05::run, &[], &[input]);
+    }
+    check("07", &day07::PROGRAM, day07::run, &[], &[4, 0]);
+    check("07", &day07::PROGRAM, day07::run, &[], &[9, 0, 5]);
+    check("07", &day07::PROGRAM, day07::run, &[], &[]);
+    for &input in &[1, 2] {
+        check("09", &day09::PROGRAM, day09::run, &[], &[input]);
+    }
+}
+"#;
+    for (path, text) in [
+        ("Cargo.toml", manifest),
+        ("build.rs", build),
+        ("src/main.rs", main.to_string()),
+    ] {
+        let path = dir.join(path);
+        if fs::read_to_string(&path).ok() != Some(text.clone()) {
+            fs::write(path, text).unwrap();
+        }
+    }
+
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let output = std::process::Command::new(cargo)
+        .args(["run", "--quiet", "--offline"])
+        .current_dir(&dir)
+        .env_remove("CARGO_TARGET_DIR")
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
@@ -0,0 +1,105 @@
+//! Fast instruction decoding.
+//!
+//! `parse_instr` splits the word into decimal digits on every call. Since
+//! there are only a few thousand valid instruction words, we decode them all
+//! once into a table and index it instead. On top of that a machine can
+//! optionally cache the decoded instruction at each address, dropping the
+//! entry whenever that address is written to.
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use crate::{parse_instr, Fault, Instr};
+
+/// Every valid instruction word is below this: three mode digits of at most
+/// 2 above a two-digit opcode.
+const TABLE_SIZE: usize = 22300;
+
+/// How a `Machine` turns instruction words into instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Decoder {
+    /// Split the word into digits every time. Slow; kept for comparison.
+    Parse,
+    /// Look the word up in a precomputed table.
+    #[default]
+    Table,
+    /// Remember the decoded instruction at each address until it is
+    /// overwritten.
+    Cached,
+}
+
+fn table() -> &'static [Result<Instr, Fault>] {
+    static TABLE: OnceLock<Vec<Result<Instr, Fault>>> = OnceLock::new();
+    TABLE.get_or_init(|| (0..TABLE_SIZE as i64).map(parse_instr).collect())
+}
+
+/// Decode a word without allocating.
+pub(crate) fn decode_word(word: i64) -> Result<Instr, Fault> {
+    if word >= 0 && (word as usize) < TABLE_SIZE {
+        table()[word as usize]
+    } else {
+        parse_instr(word)
+    }
+}
+
+/// Decoded instructions by address. Keyed sparsely, like `Memory`, since
+/// code may run at any address.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DecodeCache {
+    entries: HashMap<usize, Instr>,
+}
+
+impl DecodeCache {
+    pub fn get(&mut self, addr: usize, word: i64) -> Result<Instr, Fault> {
+        if let Some(instr) = self.entries.get(&addr) {
+            return Ok(*instr);
+        }
+        let instr = decode_word(word)?;
+        self.entries.insert(addr, instr);
+        Ok(instr)
+    }
+
+    pub fn invalidate(&mut self, addr: usize) {
+        self.entries.remove(&addr);
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear()
+    }
+}
+
+#[test]
+fn table_matches_parser() {
+    for word in -5..TABLE_SIZE as i64 + 5 {
+        assert_eq!(
+            format!("{:?}", decode_word(word)),
+            format!("{:?}", parse_instr(word))
+        );
+    }
+}
+
+#[test]
+fn cache_sees_self_modification() {
+    use crate::Machine;
+    // Increments address 9, then overwrites that first instruction with a
+    // halt. Rerunning from the top must not increment again.
+    let program = vec![1001, 9, 1, 9, 1101, 0, 99, 0, 99, 0];
+    for &decoder in &[Decoder::Parse, Decoder::Table, Decoder::Cached] {
+        let mut machine = Machine::new(program.clone()).with_decoder(decoder);
+        assert_eq!(machine.run_until_event(), Ok(crate::Event::Halted(99)));
+        machine.set_ip(0);
+        assert_eq!(machine.run_until_event(), Ok(crate::Event::Halted(99)));
+        assert_eq!(machine.peek(9), 1);
+    }
+}
+
+#[test]
+fn cache_runs_code_at_high_addresses() {
+    use crate::Machine;
+    // Writes a halt far beyond the program and jumps to it.
+    let program = vec![1101, 99, 0, 3_000_000_000, 1105, 1, 3_000_000_000];
+    for &decoder in &[Decoder::Parse, Decoder::Table, Decoder::Cached] {
+        let mut machine = Machine::new(program.clone()).with_decoder(decoder);
+        assert_eq!(machine.run_until_event(), Ok(crate::Event::Halted(1101)));
+    }
+}
@@ -0,0 +1,215 @@
+//! Turn Intcode programs back into something a human can read.
+//!
+//! Operands are written as `12` for position mode (the value at address 12),
+//! `#12` for immediate mode and `@12` for relative mode (the value at
+//! `relative_base + 12`).
+//!
+//! Decoding starts from everything statically reachable from address 0 and
+//! falls back to a linear sweep for the rest, since jumps through memory
+//! can't be followed without running the program. Words that don't decode,
+//! or that would overlap a reachable instruction, are shown as `.data`.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use crate::{decode, Instr, Mode, OpNone, OpTwo};
+
+/// One entry in a listing: either a decoded instruction or a data word.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Line {
+    Instr {
+        addr: usize,
+        instr: Instr,
+        /// The opcode word followed by the raw operands.
+        words: Vec<i64>,
+    },
+    Data {
+        addr: usize,
+        value: i64,
+    },
+}
+
+impl Line {
+    pub fn addr(&self) -> usize {
+        match *self {
+            Line::Instr { addr, .. } | Line::Data { addr, .. } => addr,
+        }
+    }
+
+    pub fn words(&self) -> &[i64] {
+        match self {
+            Line::Instr { words, .. } => words,
+            Line::Data { value, .. } => std::slice::from_ref(value),
+        }
+    }
+
+    /// The assembly text, without address or raw words.
+    pub fn text(&self) -> String {
+        match self {
+            Line::Instr { instr, words, .. } => format_instr(instr, &words[1..]),
+            Line::Data { value, .. } => format!(".data {}", value),
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let words = self
+            .words()
+            .iter()
+            .map(|w| w.to_string())
+            .collect::<Vec<_>>()
+            .join(" ");
+        write!(f, "{:>5}: {:<24} {}", self.addr(), words, self.text())
+    }
+}
+
+pub fn format_operand(mode: Mode, arg: i64) -> String {
+    match mode {
+        Mode::Position => format!("{}", arg),
+        Mode::Immediate => format!("#{}", arg),
+        Mode::Relative => format!("@{}", arg),
+    }
+}
+
+/// Render an instruction and its raw operands, e.g. `add #1, 225, @-3`.
+pub fn format_instr(instr: &Instr, args: &[i64]) -> String {
+    let operands = instr
+        .modes()
+        .into_iter()
+        .zip(args)
+        .map(|(mode, &arg)| format_operand(mode, arg))
+        .collect::<Vec<_>>()
+        .join(", ");
+    if operands.is_empty() {
+        instr.mnemonic().to_string()
+    } else {
+        format!("{} {}", instr.mnemonic(), operands)
+    }
+}
+
+/// Decode the instruction at `addr`, if there is an executable one that fits.
+fn decode_at(program: &[i64], addr: usize) -> Option<Instr> {
+    let instr = decode(*program.get(addr)?)?;
+    if addr + instr.width() <= program.len() && !instr.writes_immediate() {
+        Some(instr)
+    } else {
+        None
+    }
+}
+
+/// Where control can go after the instruction at `addr`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) struct Flow {
+    /// The next instruction, if execution can fall through to it.
+    pub fall: Option<usize>,
+    /// The target of a jump with an immediate target, if it can be taken.
+    pub jump: Option<usize>,
+    /// A jump that can be taken to a target only known at run time.
+    pub indirect: bool,
+}
+
+pub(crate) fn flow(program: &[i64], addr: usize, instr: &Instr) -> Flow {
+    let next = addr + instr.width();
+    match *instr {
+        Instr::NoneArg(OpNone::Exit) => Flow::default(),
+        Instr::TwoArg(op, (mode1, mode2)) => {
+            let (cond, target) = (program[addr + 1], program[addr + 2]);
+            let (may_jump, may_fall) = if mode1 == Mode::Immediate {
+                let taken = (cond != 0) == (op == OpTwo::JumpIfTrue);
+                (taken, !taken)
+            } else {
+                (true, true)
+            };
+            let direct = mode2 == Mode::Immediate;
+            Flow {
+                fall: Some(next).filter(|_| may_fall),
+                jump: Some(target as usize).filter(|_| may_jump && direct && target >= 0),
+                indirect: may_jump && !direct,
+            }
+        }
+        _ => Flow {
+            fall: Some(next),
+            ..Flow::default()
+        },
+    }
+}
+
+/// Find the start of every instruction reachable from address 0, following
+/// fall-through and jumps with immediate targets.
+pub(crate) fn reachable(program: &[i64]) -> BTreeSet<usize> {
+    let mut seen = BTreeSet::new();
+    let mut todo = vec![0];
+    while let Some(addr) = todo.pop() {
+        if seen.contains(&addr) {
+            continue;
+        }
+        let instr = match decode_at(program, addr) {
+            Some(instr) => instr,
+            None => continue,
+        };
+        seen.insert(addr);
+        let flow = flow(program, addr, &instr);
+        todo.extend(flow.jump);
+        todo.extend(flow.fall);
+    }
+    seen
+}
+
+/// Disassemble a whole program into a listing.
+pub fn disassemble(program: &[i64]) -> Vec<Line> {
+    let code = reachable(program);
+    let overlaps_code =
+        |addr: usize, width: usize| (addr + 1..addr + width).any(|a| code.contains(&a));
+    let mut lines = Vec::new();
+    let mut addr = 0;
+    while addr < program.len() {
+        let instr = decode_at(program, addr)
+            .filter(|instr| code.contains(&addr) || !overlaps_code(addr, instr.width()));
+        match instr {
+            Some(instr) => {
+                let width = instr.width();
+                lines.push(Line::Instr {
+                    addr,
+                    instr,
+                    words: program[addr..addr + width].to_vec(),
+                });
+                addr += width;
+            }
+            None => {
+                lines.push(Line::Data {
+                    addr,
+                    value: program[addr],
+                });
+                addr += 1;
+            }
+        }
+    }
+    lines
+}
+
+/// Disassemble a program into printable text, one line per entry.
+pub fn listing(program: &[i64]) -> String {
+    disassemble(program)
+        .iter()
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+#[test]
+fn disassembles_day05_example() {
+    let lines = disassemble(&[1002, 4, 3, 4, 33]);
+    assert_eq!(lines[0].text(), "mul 4, #3, 4");
+    assert_eq!(lines[1], Line::Data { addr: 4, value: 33 });
+}
+
+#[test]
+fn unreachable_words_are_data() {
+    // Jump straight over a word that happens to decode as `add`.
+    let lines = disassemble(&[1105, 1, 4, 1, 109, -1, 204, 1, 99]);
+    let text: Vec<_> = lines.iter().map(|l| l.text()).collect();
+    assert_eq!(
+        text,
+        vec!["jt #1, #4", ".data 1", "arb #-1", "out @1", "hlt"]
+    );
+}
@@ -0,0 +1,727 @@
+//! Differential fuzzing of `Machine` against a reference interpreter.
+//!
+//! Programs are generated as a list of units: single instructions, forward
+//! jumps, `arb` adjustments that are undone straight away, instructions that
+//! are rewritten into another once they have run, and loops run a fixed
+//! number of times, so every program halts. Operands read and write a block
+//! of scratch cells after the code, directly or relative to a base set in a
+//! prologue. Only rewrites write over code, and inside a loop the new
+//! instruction runs on the next pass, which catches stale decoded copies.
+//!
+//! Each program is run by `Machine`, once per `Decoder`, and by `reference`,
+//! an interpreter written to be obviously right rather than fast. Any
+//! difference in outputs, final memory, `ip` or how the run ended is a
+//! failure, which is shrunk to a small program that still fails.
+
+use std::fmt;
+
+use crate::rng::Rng;
+use crate::{Decoder, Event, Machine};
+
+/// Scratch cells available to generated programs.
+const SCRATCH: usize = 8;
+
+/// How a run ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    Halted(i64),
+    NeedInput,
+    Error,
+    StepLimit,
+}
+
+/// Everything compared between interpreters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub outputs: Vec<i64>,
+    /// The cells the program was loaded into.
+    pub memory: Vec<i64>,
+    pub ip: usize,
+    pub status: Status,
+}
+
+/// Run `program` on `Machine` with the given decoder.
+pub fn run_machine(program: &[i64], inputs: &[i64], max_steps: u64, decoder: Decoder) -> Report {
+    let mut machine =
+        Machine::new_with_fallible_io(program.to_vec(), || None, |_| ()).with_decoder(decoder);
+    for &val in inputs {
+        machine.provide_input(val);
+    }
+    let mut outputs = Vec::new();
+    let mut steps = 0;
+    let status = loop {
+        if steps == max_steps {
+            break Status::StepLimit;
+        }
+        match machine.step_event() {
+            Ok(None) => {}
+            Ok(Some(Event::Output(val))) => outputs.push(val),
+            Ok(Some(Event::NeedInput)) => break Status::NeedInput,
+            Ok(Some(Event::Halted(val))) => break Status::Halted(val),
+            Err(_) => break Status::Error,
+        }
+        steps += 1;
+    };
+    Report {
+        outputs,
+        memory: machine.state().to_vec(),
+        ip: machine.ip(),
+        status,
+    }
+}
+
+/// The reference interpreter's state. Memory grows on demand with no
+/// limit, which is fine for generated programs since their addresses are
+/// all small.
+struct Reference {
+    memory: Vec<i64>,
+    ip: usize,
+    relative_base: i64,
+}
+
+impl Reference {
+    fn cell(&self, addr: usize) -> i64 {
+        self.memory.get(addr).copied().unwrap_or(0)
+    }
+
+    /// The address parameter `n` refers to, or `None` in immediate mode.
+    fn address(&self, modes: &[i64], n: usize) -> Result<Option<usize>, ()> {
+        let param = self.cell(self.ip + 1 + n);
+        let addr = match modes[n] {
+            0 => param,
+            1 => return Ok(None),
+            _ => param.wrapping_add(self.relative_base),
+        };
+        if addr < 0 {
+            Err(())
+        } else {
+            Ok(Some(addr as usize))
+        }
+    }
+
+    fn read(&self, modes: &[i64], n: usize) -> Result<i64, ()> {
+        match self.address(modes, n)? {
+            Some(addr) => Ok(self.cell(addr)),
+            None => Ok(self.cell(self.ip + 1 + n)),
+        }
+    }
+
+    fn write(&mut self, modes: &[i64], n: usize, val: i64) -> Result<(), ()> {
+        let addr = self.address(modes, n)?.ok_or(())?;
+        if addr >= self.memory.len() {
+            self.memory.resize(addr + 1, 0);
+        }
+        self.memory[addr] = val;
+        Ok(())
+    }
+
+    /// Execute one instruction, returning how the run ended if it did.
+    fn step(
+        &mut self,
+        inputs: &mut dyn Iterator<Item = i64>,
+        outputs: &mut Vec<i64>,
+    ) -> Result<Option<Status>, ()> {
+        let word = self.cell(self.ip);
+        if word < 0 {
+            return Err(());
+        }
+        let opcode = word % 100;
+        let arity = match opcode {
+            1 | 2 | 7 | 8 => 3,
+            5 | 6 => 2,
+            3 | 4 | 9 => 1,
+            99 => 0,
+            _ => return Err(()),
+        };
+        // A mode for each parameter, and no stray digits above them.
+        let modes: Vec<i64> = (0..arity).map(|n| word / 10i64.pow(n + 2) % 10).collect();
+        if modes.iter().any(|&m| m > 2) || word / 10i64.pow(arity + 2) != 0 {
+            return Err(());
+        }
+        let next = self.ip + 1 + arity as usize;
+        match opcode {
+            1 | 2 | 7 | 8 => {
+                let (a, b) = (self.read(&modes, 0)?, self.read(&modes, 1)?);
+                let val = match opcode {
+                    1 => a.wrapping_add(b),
+                    2 => a.wrapping_mul(b),
+                    7 => (a < b) as i64,
+                    _ => (a == b) as i64,
+                };
+                self.write(&modes, 2, val)?;
+            }
+            3 => {
+                self.address(&modes, 0)?.ok_or(())?;
+                match inputs.next() {
+                    Some(val) => self.write(&modes, 0, val)?,
+                    None => return Ok(Some(Status::NeedInput)),
+                }
+            }
+            4 => outputs.push(self.read(&modes, 0)?),
+            5 | 6 => {
+                let (cond, to) = (self.read(&modes, 0)?, self.read(&modes, 1)?);
+                if (cond != 0) == (opcode == 5) {
+                    if to < 0 {
+                        return Err(());
+                    }
+                    self.ip = to as usize;
+                    return Ok(None);
+                }
+            }
+            9 => {
+                let adj = self.read(&modes, 0)?;
+                self.relative_base = self.relative_base.wrapping_add(adj);
+            }
+            _ => return Ok(Some(Status::Halted(self.cell(0)))),
+        }
+        self.ip = next;
+        Ok(None)
+    }
+}
+
+/// Run `program` on the reference interpreter.
+pub fn reference(program: &[i64], inputs: &[i64], max_steps: u64) -> Report {
+    let mut machine = Reference {
+        memory: program.to_vec(),
+        ip: 0,
+        relative_base: 0,
+    };
+    let mut inputs = inputs.iter().copied();
+    let mut outputs = Vec::new();
+    let mut steps = 0;
+    let status = loop {
+        if steps == max_steps {
+            break Status::StepLimit;
+        }
+        match machine.step(&mut inputs, &mut outputs) {
+            Ok(None) => steps += 1,
+            Ok(Some(status)) => break status,
+            Err(()) => break Status::Error,
+        }
+    };
+    machine.memory.truncate(program.len());
+    Report {
+        outputs,
+        memory: machine.memory,
+        ip: machine.ip,
+        status,
+    }
+}
+
+/// An operand before addresses are known.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Arg {
+    Imm(i64),
+    /// A scratch cell in position mode.
+    Scratch(usize),
+    /// A scratch cell in relative mode.
+    Rel(usize),
+}
+
+/// An instruction before addresses are known.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Op {
+    opcode: i64,
+    args: Vec<Arg>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Unit {
+    Op(Op),
+    /// `arb #shift`, the instruction, then `arb #-shift`.
+    Shifted(i64, Op),
+    /// The first instruction, then `add`s writing the second over it. Both
+    /// have the same width.
+    Rewrite(Op, Op),
+    /// Jump forward to the start of a later unit, or to the final `hlt`.
+    Jump {
+        opcode: i64,
+        cond: Arg,
+        to: usize,
+    },
+    /// Run the body `times` times, counting in a cell of its own.
+    Loop {
+        times: i64,
+        body: Vec<Unit>,
+    },
+}
+
+/// A generated program and its input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Case {
+    units: Vec<Unit>,
+    scratch: Vec<i64>,
+    inputs: Vec<i64>,
+}
+
+fn gen_value(rng: &mut Rng) -> i64 {
+    match rng.below(8) {
+        0 => rng.range(i64::MIN / 2, i64::MAX / 2),
+        1 => -rng.range(0, 10),
+        _ => rng.range(0, 10),
+    }
+}
+
+fn gen_read(rng: &mut Rng) -> Arg {
+    match rng.below(3) {
+        0 => Arg::Imm(gen_value(rng)),
+        1 => Arg::Scratch(rng.below(SCRATCH)),
+        _ => Arg::Rel(rng.below(SCRATCH)),
+    }
+}
+
+fn gen_write(rng: &mut Rng) -> Arg {
+    match rng.below(2) {
+        0 => Arg::Scratch(rng.below(SCRATCH)),
+        _ => Arg::Rel(rng.below(SCRATCH)),
+    }
+}
+
+fn gen_op(rng: &mut Rng) -> Op {
+    match rng.below(6) {
+        0 => Op {
+            opcode: 3,
+            args: vec![gen_write(rng)],
+        },
+        1 => Op {
+            opcode: 4,
+            args: vec![gen_read(rng)],
+        },
+        _ => Op {
+            opcode: [1, 2, 7, 8][rng.below(4)],
+            args: vec![gen_read(rng), gen_read(rng), gen_write(rng)],
+        },
+    }
+}
+
+/// A unit that doesn't jump.
+fn gen_straight(rng: &mut Rng) -> Unit {
+    match rng.below(6) {
+        0 => Unit::Shifted(rng.range(-3, 3), gen_op(rng)),
+        1 => {
+            let first = gen_op(rng);
+            let second = loop {
+                let op = gen_op(rng);
+                if op.args.len() == first.args.len() {
+                    break op;
+                }
+            };
+            Unit::Rewrite(first, second)
+        }
+        _ => Unit::Op(gen_op(rng)),
+    }
+}
+
+fn gen_case(rng: &mut Rng) -> Case {
+    let len = 1 + rng.below(12);
+    let mut units = Vec::new();
+    for ix in 0..len {
+        let unit = match rng.below(6) {
+            0 => Unit::Jump {
+                opcode: 5 + rng.below(2) as i64,
+                cond: gen_read(rng),
+                to: ix + 1 + rng.below(len - ix),
+            },
+            1 => Unit::Loop {
+                times: rng.range(1, 4),
+                body: (0..1 + rng.below(3)).map(|_| gen_straight(rng)).collect(),
+            },
+            _ => gen_straight(rng),
+        };
+        units.push(unit);
+    }
+    Case {
+        units,
+        scratch: (0..SCRATCH).map(|_| gen_value(rng)).collect(),
+        inputs: (0..rng.below(4)).map(|_| gen_value(rng)).collect(),
+    }
+}
+
+fn width(unit: &Unit) -> usize {
+    match unit {
+        Unit::Op(op) => 1 + op.args.len(),
+        Unit::Shifted(_, op) => 5 + op.args.len(),
+        Unit::Rewrite(first, _) => 5 * (1 + first.args.len()),
+        Unit::Jump { .. } => 3,
+        Unit::Loop { body, .. } => 4 + body.iter().map(width).sum::<usize>() + 4 + 3,
+    }
+}
+
+fn count_loops(units: &[Unit]) -> usize {
+    units
+        .iter()
+        .filter(|u| matches!(u, Unit::Loop { .. }))
+        .count()
+}
+
+/// Lays out a case as Intcode: a prologue setting the relative base to the
+/// scratch cells, the units, `hlt`, the scratch cells, then loop counters.
+struct Emitter {
+    words: Vec<i64>,
+    scratch: i64,
+    counters: usize,
+    /// How far the relative base currently is from the scratch cells.
+    shift: i64,
+}
+
+impl Emitter {
+    fn instr(&mut self, opcode: i64, args: &[(i64, i64)]) {
+        let modes = args
+            .iter()
+            .enumerate()
+            .map(|(n, &(mode, _))| mode * 10i64.pow(n as u32 + 2))
+            .sum::<i64>();
+        self.words.push(opcode + modes);
+        self.words.extend(args.iter().map(|&(_, val)| val));
+    }
+
+    fn arg(&self, arg: Arg) -> (i64, i64) {
+        match arg {
+            Arg::Imm(val) => (1, val),
+            Arg::Scratch(ix) => (0, self.scratch + ix as i64),
+            Arg::Rel(ix) => (2, ix as i64 - self.shift),
+        }
+    }
+
+    fn op(&mut self, op: &Op) {
+        let args: Vec<_> = op.args.iter().map(|&a| self.arg(a)).collect();
+        self.instr(op.opcode, &args);
+    }
+
+    /// The words `op` would be emitted as.
+    fn encode(&self, op: &Op) -> Vec<i64> {
+        let mut scratch = Emitter {
+            words: Vec::new(),
+            ..*self
+        };
+        scratch.op(op);
+        scratch.words
+    }
+
+    fn unit(&mut self, unit: &Unit, starts: &[usize]) {
+        match unit {
+            Unit::Op(op) => self.op(op),
+            Unit::Shifted(shift, op) => {
+                self.instr(9, &[(1, *shift)]);
+                self.shift += shift;
+                self.op(op);
+                self.shift -= shift;
+                self.instr(9, &[(1, -shift)]);
+            }
+            Unit::Rewrite(first, second) => {
+                let at = self.words.len() as i64;
+                self.op(first);
+                for (ix, word) in self.encode(second).into_iter().enumerate() {
+                    self.instr(1, &[(1, word), (1, 0), (0, at + ix as i64)]);
+                }
+            }
+            Unit::Jump { opcode, cond, to } => {
+                let cond = self.arg(*cond);
+                self.instr(*opcode, &[cond, (1, starts[*to] as i64)]);
+            }
+            Unit::Loop { times, body } => {
+                let counter = self.scratch + (SCRATCH + self.counters) as i64;
+                self.counters += 1;
+                self.instr(1, &[(1, *times), (1, 0), (0, counter)]);
+                let top = self.words.len() as i64;
+                for unit in body {
+                    self.unit(unit, starts);
+                }
+                self.instr(1, &[(0, counter), (1, -1), (0, counter)]);
+                self.instr(5, &[(0, counter), (1, top)]);
+            }
+        }
+    }
+}
+
+impl Case {
+    fn program(&self) -> Vec<i64> {
+        let mut starts = vec![2];
+        for unit in &self.units {
+            starts.push(starts.last().unwrap() + width(unit));
+        }
+        let end = *starts.last().unwrap();
+        let mut emitter = Emitter {
+            words: Vec::new(),
+            scratch: end as i64 + 1,
+            counters: 0,
+            shift: 0,
+        };
+        emitter.instr(9, &[(1, emitter.scratch)]);
+        for unit in &self.units {
+            emitter.unit(unit, &starts);
+        }
+        emitter.instr(99, &[]);
+        let mut words = emitter.words;
+        words.extend(&self.scratch);
+        words.extend(std::iter::repeat_n(0, count_loops(&self.units)));
+        words
+    }
+
+    /// Smaller cases to try in place of this one, most drastic first.
+    fn shrinks(&self) -> Vec<Case> {
+        let mut smaller = Vec::new();
+        let with_units = |units: Vec<Unit>| Case {
+            units,
+            ..self.clone()
+        };
+        for ix in 0..self.units.len() {
+            // Drop the unit, keeping jumps pointing at the same code.
+            let mut units = self.units.clone();
+            units.remove(ix);
+            for unit in &mut units {
+                if let Unit::Jump { to, .. } = unit {
+                    if *to > ix {
+                        *to -= 1;
+                    }
+                }
+            }
+            smaller.push(with_units(units));
+            match &self.units[ix] {
+                Unit::Loop { body, times } => {
+                    // Run the body once, without the loop.
+                    let mut units = self.units.clone();
+                    units.splice(ix..=ix, body.iter().cloned());
+                    for unit in &mut units {
+                        if let Unit::Jump { to, .. } = unit {
+                            if *to > ix {
+                                *to = *to + body.len() - 1;
+                            }
+                        }
+                    }
+                    smaller.push(with_units(units));
+                    if *times > 1 {
+                        let mut units = self.units.clone();
+                        units[ix] = Unit::Loop {
+                            times: 1,
+                            body: body.clone(),
+                        };
+                        smaller.push(with_units(units));
+                    }
+                    for drop in 0..body.len() {
+                        let mut body = body.clone();
+                        body.remove(drop);
+                        if !body.is_empty() {
+                            let mut units = self.units.clone();
+                            units[ix] = Unit::Loop {
+                                times: *times,
+                                body,
+                            };
+                            smaller.push(with_units(units));
+                        }
+                    }
+                }
+                Unit::Shifted(_, op) => {
+                    let mut units = self.units.clone();
+                    units[ix] = Unit::Op(op.clone());
+                    smaller.push(with_units(units));
+                }
+                Unit::Rewrite(first, second) => {
+                    for op in [first, second] {
+                        let mut units = self.units.clone();
+                        units[ix] = Unit::Op(op.clone());
+                        smaller.push(with_units(units));
+                    }
+                }
+                _ => {}
+            }
+        }
+        if !self.inputs.is_empty() {
+            let mut inputs = self.inputs.clone();
+            inputs.pop();
+            smaller.push(Case {
+                inputs,
+                ..self.clone()
+            });
+        }
+        for ix in 0..self.scratch.len() {
+            if self.scratch[ix] != 0 {
+                let mut scratch = self.scratch.clone();
+                scratch[ix] = 0;
+                smaller.push(Case {
+                    scratch,
+                    ..self.clone()
+                });
+            }
+        }
+        smaller
+    }
+}
+
+/// A program on which an interpreter disagreed with the reference.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    /// Which generated case failed, counting from 0.
+    pub case: usize,
+    /// The failing program, after shrinking.
+    pub program: Vec<i64>,
+    pub inputs: Vec<i64>,
+    pub expected: Report,
+    pub actual: Report,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let program: Vec<_> = self.program.iter().map(|w| w.to_string()).collect();
+        writeln!(f, "Case {} failed", self.case)?;
+        writeln!(f, "program:  {}", program.join(","))?;
+        writeln!(f, "inputs:   {:?}", self.inputs)?;
+        writeln!(f, "expected: {:?}", self.expected)?;
+        write!(f, "actual:   {:?}", self.actual)
+    }
+}
+
+/// An interpreter under test, given a program, its inputs and a step limit.
+type Subject<'a> = &'a dyn Fn(&[i64], &[i64], u64) -> Report;
+
+pub struct Fuzzer {
+    rng: Rng,
+    cases: usize,
+    max_steps: u64,
+}
+
+impl Fuzzer {
+    pub fn new(seed: u64) -> Self {
+        Fuzzer {
+            rng: Rng::new(seed),
+            cases: 1000,
+            max_steps: 10_000,
+        }
+    }
+
+    pub fn with_cases(mut self, cases: usize) -> Self {
+        self.cases = cases;
+        self
+    }
+
+    /// Bound each run, in case a bug stops a program halting.
+    pub fn with_max_steps(mut self, steps: u64) -> Self {
+        self.max_steps = steps;
+        self
+    }
+
+    /// Check `Machine`, with each decoder, against the reference.
+    pub fn run(&mut self) -> Result<(), Box<Failure>> {
+        let decoders = [Decoder::Parse, Decoder::Table, Decoder::Cached];
+        for case in 0..self.cases {
+            let generated = gen_case(&mut self.rng);
+            for &decoder in &decoders {
+                let subject = |program: &[i64], inputs: &[i64], max_steps: u64| {
+                    run_machine(program, inputs, max_steps, decoder)
+                };
+                self.check(case, &generated, &subject)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check any interpreter against the reference.
+    pub fn run_against(
+        &mut self,
+        subject: impl Fn(&[i64], &[i64], u64) -> Report,
+    ) -> Result<(), Box<Failure>> {
+        for case in 0..self.cases {
+            let generated = gen_case(&mut self.rng);
+            self.check(case, &generated, &subject)?;
+        }
+        Ok(())
+    }
+
+    fn check(&self, case: usize, generated: &Case, subject: Subject) -> Result<(), Box<Failure>> {
+        let failure = |c: &Case| {
+            let program = c.program();
+            let expected = reference(&program, &c.inputs, self.max_steps);
+            let actual = subject(&program, &c.inputs, self.max_steps);
+            Some(Failure {
+                case,
+                program,
+                inputs: c.inputs.clone(),
+                expected,
+                actual,
+            })
+            .filter(|f| f.expected != f.actual)
+        };
+        let mut found = match failure(generated) {
+            Some(found) => found,
+            None => return Ok(()),
+        };
+        // Take the first smaller case that still fails, until none do.
+        let mut current = generated.clone();
+        while let Some((smaller, failed)) = current
+            .shrinks()
+            .into_iter()
+            .find_map(|c| failure(&c).map(|f| (c, f)))
+        {
+            current = smaller;
+            found = failed;
+        }
+        Err(Box::new(found))
+    }
+}
+
+#[test]
+fn machine_matches_reference() {
+    if let Err(failure) = Fuzzer::new(2019).with_cases(300).run() {
+        panic!("{}", failure);
+    }
+}
+
+#[test]
+fn generated_programs_halt() {
+    let mut rng = Rng::new(7);
+    for _ in 0..200 {
+        let case = gen_case(&mut rng);
+        let report = reference(&case.program(), &case.inputs, 10_000);
+        assert!(
+            matches!(report.status, Status::Halted(_) | Status::NeedInput),
+            "{:?}",
+            report
+        );
+    }
+}
+
+#[test]
+fn rewrites_code_that_runs_again() {
+    // Output 1, then turn that into `out @3` for the second pass.
+    let case = Case {
+        units: vec![Unit::Loop {
+            times: 2,
+            body: vec![Unit::Rewrite(
+                Op {
+                    opcode: 4,
+                    args: vec![Arg::Imm(1)],
+                },
+                Op {
+                    opcode: 4,
+                    args: vec![Arg::Rel(3)],
+                },
+            )],
+        }],
+        scratch: vec![0, 0, 0, 7, 0, 0, 0, 0],
+        inputs: vec![],
+    };
+    let program = case.program();
+    let expected = reference(&program, &[], 1000);
+    assert_eq!(expected.outputs, vec![1, 7]);
+    for &decoder in &[Decoder::Parse, Decoder::Table, Decoder::Cached] {
+        assert_eq!(run_machine(&program, &[], 1000, decoder), expected);
+    }
+}
+
+#[test]
+fn shrinks_failures() {
+    // An interpreter that loses zero outputs.
+    let buggy = |program: &[i64], inputs: &[i64], max_steps: u64| {
+        let mut report = run_machine(program, inputs, max_steps, Decoder::Table);
+        report.outputs.retain(|&v| v != 0);
+        report
+    };
+    let failure = Fuzzer::new(1).run_against(buggy).unwrap_err();
+    assert_eq!(failure.expected.outputs, vec![0]);
+    assert!(failure.actual.outputs.is_empty());
+    // The prologue, a single `out`, `hlt` and the scratch cells.
+    assert_eq!(failure.program.len(), 2 + 2 + 1 + SCRATCH, "{}", failure);
+    assert!(failure
+        .to_string()
+        .starts_with(&format!("Case {} failed", failure.case)));
+}
@@ -0,0 +1,315 @@
+//! A compact binary form for programs and machine snapshots.
+//!
+//! All numbers are little-endian. The header is:
+//!
+//! | bytes | field                                   |
+//! |-------|-----------------------------------------|
+//! | 4     | magic `ICIM`                            |
+//! | 2     | format version, currently 2             |
+//! | 2     | reserved, zero                          |
+//! | 8     | `ip`                                    |
+//! | 8     | `relative_base`                         |
+//! | 8     | program length (`initial_size`)         |
+//! | 8     | memory limit                            |
+//! | 8     | number of queued inputs                 |
+//! | 8     | number of dense memory cells            |
+//! | 8     | number of sparse memory cells           |
+//! | 8     | FNV-1a checksum of every other byte     |
+//!
+//! followed by the queued inputs, the dense cells, and the sparse cells as
+//! address/value pairs, each 8 bytes.
+//!
+//! There is no room for cells holding values too large for an `i64` under
+//! `Arithmetic::Big`, so a snapshot with any is refused; the text form of
+//! `MachineSnapshot` keeps them exactly.
+
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::memory::Memory;
+use crate::{Machine, MachineSnapshot};
+
+pub const MAGIC: &[u8; 4] = b"ICIM";
+pub const VERSION: u16 = 2;
+const HEADER_LEN: usize = 72;
+
+#[derive(Debug)]
+pub enum ImageError {
+    Io(io::Error),
+    /// The data doesn't start with `MAGIC`.
+    NotAnImage,
+    UnsupportedVersion(u16),
+    /// The data ends before the header says it should.
+    Truncated,
+    ChecksumMismatch {
+        expected: u64,
+        actual: u64,
+    },
+    /// The header is inconsistent, e.g. the program is longer than memory.
+    Invalid(String),
+    /// The snapshot has this many cells too large for an `i64`.
+    ExactCells(usize),
+}
+
+impl fmt::Display for ImageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImageError::Io(e) => write!(f, "{}", e),
+            ImageError::NotAnImage => write!(f, "Not an Intcode image"),
+            ImageError::UnsupportedVersion(v) => write!(f, "Unsupported image version {}", v),
+            ImageError::Truncated => write!(f, "Image is truncated"),
+            ImageError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "Image checksum mismatch (expected {:016x}, got {:016x})",
+                expected, actual
+            ),
+            ImageError::Invalid(message) => write!(f, "Invalid image: {}", message),
+            ImageError::ExactCells(count) => write!(
+                f,
+                "{} cells are too large for an image; save snapshot text instead",
+                count
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ImageError {}
+
+impl From<io::Error> for ImageError {
+    fn from(e: io::Error) -> Self {
+        ImageError::Io(e)
+    }
+}
+
+/// Checksum the header up to the checksum field, then the payload.
+fn checksum(header: &[u8], payload: &[u8]) -> u64 {
+    header
+        .iter()
+        .chain(payload)
+        .fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+            (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+/// Whether `bytes` look like an image rather than text.
+pub fn is_image(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Reads fixed-size little-endian fields from the front of a slice.
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], ImageError> {
+        if self.0.len() < len {
+            return Err(ImageError::Truncated);
+        }
+        let (head, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(head)
+    }
+
+    fn u64(&mut self) -> Result<u64, ImageError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn i64(&mut self) -> Result<i64, ImageError> {
+        Ok(self.u64()? as i64)
+    }
+
+    fn usize(&mut self, what: &str) -> Result<usize, ImageError> {
+        let val = self.u64()?;
+        val.try_into()
+            .map_err(|_| ImageError::Invalid(format!("{} {} is too large", what, val)))
+    }
+
+    /// Check there are at least `count` items of `width` bytes left, before
+    /// trusting a count enough to loop over it.
+    fn holds(&self, count: usize, width: usize) -> Result<(), ImageError> {
+        match count.checked_mul(width) {
+            Some(len) if len <= self.0.len() => Ok(()),
+            _ => Err(ImageError::Truncated),
+        }
+    }
+}
+
+impl MachineSnapshot {
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ImageError> {
+        if !self.memory.exact().is_empty() {
+            return Err(ImageError::ExactCells(self.memory.exact().len()));
+        }
+        let mut payload = Vec::new();
+        for &val in self.inputs.iter().chain(self.memory.dense()) {
+            payload.extend_from_slice(&val.to_le_bytes());
+        }
+        for (&addr, &val) in self.memory.sparse() {
+            payload.extend_from_slice(&(addr as u64).to_le_bytes());
+            payload.extend_from_slice(&val.to_le_bytes());
+        }
+
+        let mut bytes = Vec::with_capacity(HEADER_LEN + payload.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&0u16.to_le_bytes());
+        for field in [
+            self.ip as u64,
+            self.relative_base as u64,
+            self.initial_size as u64,
+            self.memory.limit() as u64,
+            self.inputs.len() as u64,
+            self.memory.dense().len() as u64,
+            self.memory.sparse().len() as u64,
+        ] {
+            bytes.extend_from_slice(&field.to_le_bytes());
+        }
+        let sum = checksum(&bytes, &payload);
+        bytes.extend_from_slice(&sum.to_le_bytes());
+        bytes.extend_from_slice(&payload);
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ImageError> {
+        if !is_image(bytes) {
+            return Err(ImageError::NotAnImage);
+        }
+        let mut header = Reader(&bytes[MAGIC.len()..]);
+        let version = u16::from_le_bytes(header.take(2)?.try_into().unwrap());
+        if version != VERSION {
+            return Err(ImageError::UnsupportedVersion(version));
+        }
+        header.take(2)?;
+        let ip = header.usize("ip")?;
+        let relative_base = header.i64()?;
+        let initial_size = header.usize("program length")?;
+        let limit = header.usize("memory limit")?;
+        let ninputs = header.usize("input count")?;
+        let ndense = header.usize("memory length")?;
+        let nsparse = header.usize("sparse count")?;
+        let expected = header.u64()?;
+        let actual = checksum(&bytes[..HEADER_LEN - 8], header.0);
+        if actual != expected {
+            return Err(ImageError::ChecksumMismatch { expected, actual });
+        }
+
+        let mut payload = header;
+        payload.holds(ninputs, 8)?;
+        let inputs = (0..ninputs)
+            .map(|_| payload.i64())
+            .collect::<Result<_, _>>()?;
+        payload.holds(ndense, 8)?;
+        let dense: Vec<i64> = (0..ndense)
+            .map(|_| payload.i64())
+            .collect::<Result<_, _>>()?;
+        payload.holds(nsparse, 16)?;
+        let sparse: BTreeMap<usize, i64> = (0..nsparse)
+            .map(|_| Ok((payload.usize("address")?, payload.i64()?)))
+            .collect::<Result<_, ImageError>>()?;
+        if !payload.0.is_empty() {
+            return Err(ImageError::Invalid("trailing data".to_string()));
+        }
+        if initial_size > dense.len() {
+            return Err(ImageError::Invalid(format!(
+                "program length {} exceeds memory length {}",
+                initial_size,
+                dense.len()
+            )));
+        }
+
+        Ok(MachineSnapshot {
+            ip,
+            relative_base,
+            inputs,
+            memory: Memory::from_parts(dense, sparse, limit).map_err(ImageError::Invalid)?,
+            initial_size,
+        })
+    }
+}
+
+impl Machine {
+    /// Save memory, registers and queued input as a binary image.
+    pub fn save_image(&self, path: impl AsRef<Path>) -> Result<(), ImageError> {
+        Ok(fs::write(path, self.snapshot().to_bytes()?)?)
+    }
+
+    /// Load a machine saved with `save_image`, with the default I/O.
+    pub fn load_image(path: impl AsRef<Path>) -> Result<Self, ImageError> {
+        let snapshot = MachineSnapshot::from_bytes(&fs::read(path)?)?;
+        let mut machine = Machine::new(Vec::new());
+        machine.restore(&snapshot);
+        Ok(machine)
+    }
+}
+
+#[test]
+fn images_round_trip() {
+    let mut machine =
+        Machine::new(vec![3, 9, 1001, 9, 1, 9, 4, 9, 99, 0]).with_memory_limit(1 << 30);
+    machine.poke(1 << 25, -7);
+    machine.set_relative_base(-3);
+    machine.provide_input(41);
+    machine.provide_input(5);
+    machine.step_event().unwrap();
+    let snapshot = machine.snapshot();
+    let bytes = snapshot.to_bytes().unwrap();
+    assert!(is_image(&bytes));
+    assert_eq!(MachineSnapshot::from_bytes(&bytes).unwrap(), snapshot);
+
+    let program = Machine::from_file("data/09").snapshot();
+    let bytes = program.to_bytes().unwrap();
+    assert_eq!(bytes.len(), HEADER_LEN + 8 * program.state().len());
+    assert_eq!(MachineSnapshot::from_bytes(&bytes).unwrap(), program);
+}
+
+#[test]
+fn rejects_damaged_images() {
+    let bytes = Machine::new(vec![1, 0, 0, 0, 99])
+        .snapshot()
+        .to_bytes()
+        .unwrap();
+    assert!(matches!(
+        MachineSnapshot::from_bytes(b"1,0,0,0,99"),
+        Err(ImageError::NotAnImage)
+    ));
+    assert!(matches!(
+        MachineSnapshot::from_bytes(&bytes[..bytes.len() - 1]),
+        Err(ImageError::ChecksumMismatch { .. })
+    ));
+    assert!(matches!(
+        MachineSnapshot::from_bytes(&bytes[..20]),
+        Err(ImageError::Truncated)
+    ));
+    let mut corrupt = bytes.clone();
+    *corrupt.last_mut().unwrap() ^= 1;
+    assert!(matches!(
+        MachineSnapshot::from_bytes(&corrupt),
+        Err(ImageError::ChecksumMismatch { .. })
+    ));
+    let mut corrupt = bytes.clone();
+    corrupt[8] ^= 1;
+    assert!(matches!(
+        MachineSnapshot::from_bytes(&corrupt),
+        Err(ImageError::ChecksumMismatch { .. })
+    ));
+    let mut future = bytes;
+    future[4] = 3;
+    assert!(matches!(
+        MachineSnapshot::from_bytes(&future),
+        Err(ImageError::UnsupportedVersion(3))
+    ));
+}
+
+#[test]
+fn refuses_exact_cells() {
+    use crate::Arithmetic;
+    let mut machine =
+        Machine::new(vec![1002, 5, 1 << 62, 5, 99, 4]).with_arithmetic(Arithmetic::Big);
+    machine.run();
+    assert!(matches!(
+        machine.snapshot().to_bytes(),
+        Err(ImageError::ExactCells(1))
+    ));
+}
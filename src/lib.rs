@@ -1,28 +1,203 @@
+use std::collections::VecDeque;
 use std::convert::TryFrom;
+use std::fmt;
 use std::io::{self, BufRead, Write};
 
+use bigint::BigInt;
+use decode::DecodeCache;
+pub use decode::Decoder;
+pub use limits::{CancelToken, Limits, Outcome, StopReason};
+pub use load::{load_program, parse_program, read_program, LoadError, ParseError};
+use memory::Memory;
+use selfmod::SelfModTracker;
+pub use selfmod::SelfModification;
+pub use snapshot::MachineSnapshot;
+pub use trace::{Profiler, TraceEntry, Tracer};
+
+pub mod adapters;
+pub mod asm;
+pub mod bigint;
+pub mod cfg;
+pub mod compile;
+mod decode;
+pub mod disasm;
+pub mod fuzz;
+pub mod image;
+mod limits;
+mod load;
+mod memory;
+pub mod network;
+pub mod phases;
+mod rng;
+pub mod schedule;
+mod selfmod;
+mod snapshot;
+pub mod symbolic;
+pub mod trace;
+
 pub struct Machine {
-    data: Vec<i64>,
+    data: Memory,
     ip: usize,
     relative_base: i64,
-    input_callback: Box<dyn FnMut() -> i64 + Send>,
+    inputs: VecDeque<i64>,
+    input_callback: Box<dyn FnMut() -> Option<i64> + Send>,
     output_callback: Box<dyn FnMut(i64) + Send>,
-    initial_size: usize
+    initial_size: usize,
+    tracer: Option<Box<dyn Tracer>>,
+    effects: Effects,
+    decoder: Decoder,
+    cache: DecodeCache,
+    arithmetic: Arithmetic,
+    selfmod: Option<Box<SelfModTracker>>,
+    exact_output: Option<BigInt>,
+}
+
+/// Operand values read and the cell written by the instruction currently
+/// executing, kept for the tracer.
+#[derive(Debug, Clone, Copy, Default)]
+struct Effects {
+    reads: [i64; 3],
+    nreads: usize,
+    write: Option<(usize, i64)>,
+}
+
+/// Everything that can go wrong while executing an instruction. Each variant
+/// carries the address of the offending instruction and its raw word.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MachineError {
+    BadOpcode { ip: usize, instr: i64 },
+    BadMode { ip: usize, instr: i64, mode: u8 },
+    WriteInImmediateMode { ip: usize, instr: i64 },
+    NegativeAddress { ip: usize, instr: i64, addr: i64 },
+    AddressOutOfBounds { ip: usize, instr: i64, addr: i64 },
+    InputExhausted { ip: usize, instr: i64 },
+    Overflow { ip: usize, instr: i64 },
+}
+
+impl MachineError {
+    pub fn ip(&self) -> usize {
+        use MachineError::*;
+        match *self {
+            BadOpcode { ip, .. }
+            | BadMode { ip, .. }
+            | WriteInImmediateMode { ip, .. }
+            | NegativeAddress { ip, .. }
+            | AddressOutOfBounds { ip, .. }
+            | InputExhausted { ip, .. }
+            | Overflow { ip, .. } => ip,
+        }
+    }
+
+    pub fn instr(&self) -> i64 {
+        use MachineError::*;
+        match *self {
+            BadOpcode { instr, .. }
+            | BadMode { instr, .. }
+            | WriteInImmediateMode { instr, .. }
+            | NegativeAddress { instr, .. }
+            | AddressOutOfBounds { instr, .. }
+            | InputExhausted { instr, .. }
+            | Overflow { instr, .. } => instr,
+        }
+    }
+}
+
+impl fmt::Display for MachineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use MachineError::*;
+        match self {
+            BadOpcode { .. } => write!(f, "Bad opcode")?,
+            BadMode { mode, .. } => write!(f, "Bad mode {}", mode)?,
+            WriteInImmediateMode { .. } => write!(f, "Write in immediate mode")?,
+            NegativeAddress { addr, .. } => write!(f, "Negative address {}", addr)?,
+            AddressOutOfBounds { addr, .. } => write!(f, "Address {} out of bounds", addr)?,
+            InputExhausted { .. } => write!(f, "Input exhausted")?,
+            Overflow { .. } => write!(f, "Arithmetic overflow")?,
+        }
+        write!(f, " (instruction {} at ip {})", self.instr(), self.ip())
+    }
+}
+
+impl std::error::Error for MachineError {}
+
+/// Why `Machine::run_until_event` handed control back to the caller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// The next instruction is an input and no value has been provided.
+    NeedInput,
+    Output(i64),
+    /// The machine hit opcode 99; carries the value at address 0.
+    Halted(i64),
+}
+
+/// What happens when an addition or multiplication, including the address
+/// arithmetic of relative mode, doesn't fit in an `i64`. Either way the
+/// result no longer depends on whether overflow checks are compiled in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Arithmetic {
+    /// Wrap around in two's complement.
+    #[default]
+    Wrapping,
+    /// Stop with `MachineError::Overflow`.
+    Checked,
+    /// Keep the exact result, however large. A cell holding a value that
+    /// doesn't fit reads as its low 64 bits, as with `Wrapping`, and
+    /// `Machine::peek_exact` and `Machine::exact_output` give the whole
+    /// value. Using such a value as an address, jump target or relative base
+    /// offset stops with `MachineError::Overflow`, and as an instruction with
+    /// `MachineError::BadOpcode`. Address arithmetic is checked.
+    Big,
 }
 
-fn read_from_stdin() -> i64 {
+impl Arithmetic {
+    fn add(self, a: i64, b: i64) -> Result<i64, Fault> {
+        match self {
+            Arithmetic::Wrapping => Ok(a.wrapping_add(b)),
+            Arithmetic::Checked | Arithmetic::Big => a.checked_add(b).ok_or(Fault::Overflow),
+        }
+    }
+
+    fn mul(self, a: i64, b: i64) -> Result<i64, Fault> {
+        match self {
+            Arithmetic::Wrapping => Ok(a.wrapping_mul(b)),
+            Arithmetic::Checked | Arithmetic::Big => a.checked_mul(b).ok_or(Fault::Overflow),
+        }
+    }
+}
+
+/// A fault raised part-way through an instruction, before we know where it
+/// happened. `Machine::try_step` attaches the ip and instruction word.
+#[derive(Debug, Clone, Copy)]
+enum Fault {
+    BadOpcode,
+    BadMode(u8),
+    WriteInImmediateMode,
+    NegativeAddress(i64),
+    AddressOutOfBounds(i64),
+    Overflow,
+}
+
+impl Fault {
+    fn at(self, ip: usize, instr: i64) -> MachineError {
+        match self {
+            Fault::BadOpcode => MachineError::BadOpcode { ip, instr },
+            Fault::BadMode(mode) => MachineError::BadMode { ip, instr, mode },
+            Fault::WriteInImmediateMode => MachineError::WriteInImmediateMode { ip, instr },
+            Fault::NegativeAddress(addr) => MachineError::NegativeAddress { ip, instr, addr },
+            Fault::AddressOutOfBounds(addr) => MachineError::AddressOutOfBounds { ip, instr, addr },
+            Fault::Overflow => MachineError::Overflow { ip, instr },
+        }
+    }
+}
+
+fn read_from_stdin() -> Option<i64> {
     loop {
         print!("Input > ");
         io::stdout().flush().unwrap();
         let stdin = io::stdin();
-        let line = stdin
-            .lock()
-            .lines()
-            .next()
-            .expect("No line")
-            .expect("Bad read");
+        let line = stdin.lock().lines().next()?.expect("Bad read");
         match line.trim().parse::<i64>() {
-            Ok(num) => break num,
+            Ok(num) => break Some(num),
             Err(_) => println!("Bad input"),
         }
     }
@@ -30,7 +205,11 @@ fn read_from_stdin() -> i64 {
 
 impl Machine {
     pub fn new(data: Vec<i64>) -> Self {
-        Self::new_with_io(data, Box::new(read_from_stdin), Box::new(|v| println!("{}", v)))
+        Self::new_with_fallible_io(
+            data,
+            Box::new(read_from_stdin),
+            Box::new(|v| println!("{}", v)),
+        )
     }
 
     pub fn init(mut data: Vec<i64>, v1: i64, v2: i64) -> Self {
@@ -39,162 +218,415 @@ impl Machine {
         Self::new(data)
     }
 
+    /// Load a program, panicking with the location of any problem. Use
+    /// `Machine::load` to handle errors instead.
     pub fn from_file(path: &str) -> Self {
-        let data = std::fs::read_to_string(path)
-            .unwrap()
-            .trim()
-            .split(',')
-            .map(|line| line.parse::<i64>().unwrap())
-            .collect();
-        Self::new(data)
+        match Self::load(path) {
+            Ok(machine) => machine,
+            Err(e) => panic!("{}: {}", path, e),
+        }
     }
 
     pub fn init_from_file(path: &str, v1: i64, v2: i64) -> Self {
         let machine = Self::from_file(path);
-        Machine::init(machine.data, v1, v2)
+        Machine::init(machine.state().to_vec(), v1, v2)
     }
 
     pub fn new_with_io(
-        mut data: Vec<i64>,
-        input: impl FnMut() -> i64 + 'static + Send,
+        data: Vec<i64>,
+        mut input: impl FnMut() -> i64 + 'static + Send,
+        output: impl FnMut(i64) + 'static + Send,
+    ) -> Self {
+        Self::new_with_fallible_io(data, move || Some(input()), output)
+    }
+
+    /// Like `new_with_io`, but the input callback may return `None` to signal
+    /// that it has run dry, which surfaces as `MachineError::InputExhausted`.
+    pub fn new_with_fallible_io(
+        data: Vec<i64>,
+        input: impl FnMut() -> Option<i64> + 'static + Send,
         output: impl FnMut(i64) + 'static + Send,
     ) -> Self {
         let initial_size = data.len();
-        data.extend(std::iter::repeat(0).take(1024 * 8));
         Self {
-            data,
+            data: Memory::new(data),
             ip: 0,
             relative_base: 0,
+            inputs: VecDeque::new(),
             input_callback: Box::new(input),
             output_callback: Box::new(output),
-            initial_size
+            initial_size,
+            tracer: None,
+            effects: Effects::default(),
+            decoder: Decoder::default(),
+            cache: DecodeCache::default(),
+            arithmetic: Arithmetic::default(),
+            selfmod: None,
+            exact_output: None,
         }
     }
 
+    /// Cap addressable memory at `limit` cells. Any access at or beyond it
+    /// fails with `MachineError::AddressOutOfBounds`.
+    pub fn with_memory_limit(mut self, limit: usize) -> Self {
+        self.data.set_limit(limit);
+        self
+    }
+
+    /// Choose how instructions are decoded; see `Decoder`.
+    pub fn with_decoder(mut self, decoder: Decoder) -> Self {
+        self.decoder = decoder;
+        self.cache.clear();
+        self
+    }
+
+    /// Choose how arithmetic overflow is handled; see `Arithmetic`.
+    pub fn with_arithmetic(mut self, arithmetic: Arithmetic) -> Self {
+        self.arithmetic = arithmetic;
+        self
+    }
+
+    pub fn arithmetic(&self) -> Arithmetic {
+        self.arithmetic
+    }
+
     pub fn state(&self) -> &[i64] {
-        &self.data[..self.initial_size]
+        &self.data.dense()[..self.initial_size]
+    }
+
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    pub fn set_ip(&mut self, ip: usize) {
+        self.ip = ip
+    }
+
+    pub fn relative_base(&self) -> i64 {
+        self.relative_base
+    }
+
+    pub fn set_relative_base(&mut self, relative_base: i64) {
+        self.relative_base = relative_base
+    }
+
+    pub fn memory_limit(&self) -> usize {
+        self.data.limit()
+    }
+
+    /// Read a memory cell directly. Panics beyond the memory limit.
+    pub fn peek(&self, addr: usize) -> i64 {
+        self.data.get(addr).expect("Address out of bounds")
+    }
+
+    /// Read a memory cell directly, including values too large for an `i64`
+    /// under `Arithmetic::Big`. Panics beyond the memory limit.
+    pub fn peek_exact(&self, addr: usize) -> BigInt {
+        match self.data.get_exact(addr) {
+            Some(val) => val.clone(),
+            None => self.peek(addr).into(),
+        }
+    }
+
+    /// Under `Arithmetic::Big`, the exact value of the most recent output,
+    /// whose `Event::Output` carries only the low 64 bits.
+    pub fn exact_output(&self) -> Option<&BigInt> {
+        self.exact_output.as_ref()
+    }
+
+    /// Write a memory cell directly. Panics beyond the memory limit.
+    pub fn poke(&mut self, addr: usize, val: i64) {
+        self.set(addr, val).expect("Address out of bounds")
     }
 
     pub fn step(&mut self) -> Option<i64> {
-        match parse_instr(self.fetch(Mode::Immediate)).unwrap() {
-            Instr::NoneArg(OpNone::Exit) => return Some(self.get(0)),
+        match self.try_step() {
+            Ok(v) => v,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Execute a single instruction, using the I/O callbacks for any input or
+    /// output. On error the machine is left pointing at the offending
+    /// instruction.
+    pub fn try_step(&mut self) -> Result<Option<i64>, MachineError> {
+        let event = match self.step_event()? {
+            Some(Event::NeedInput) => {
+                let (ip, instr) = (self.ip, self.get(self.ip).unwrap());
+                let num =
+                    (self.input_callback)().ok_or(MachineError::InputExhausted { ip, instr })?;
+                self.provide_input(num);
+                self.step_event()?
+            }
+            other => other,
+        };
+        match event {
+            Some(Event::Output(v)) => (self.output_callback)(v),
+            Some(Event::Halted(v)) => return Ok(Some(v)),
+            _ => (),
+        }
+        Ok(None)
+    }
+
+    /// Execute a single instruction without touching the I/O callbacks.
+    /// Input is taken from values queued with `provide_input`; if there are
+    /// none, or the machine has halted, it stays where it is and reports
+    /// the event, so calling again is safe.
+    pub fn step_event(&mut self) -> Result<Option<Event>, MachineError> {
+        let ip = self.ip;
+        let instr = self.get(ip).map_err(|f| f.at(ip, 0))?;
+        self.effects = Effects::default();
+        match self.exec(ip, instr) {
+            Ok(event) => {
+                if event != Some(Event::NeedInput) && self.tracer.is_some() {
+                    self.trace(ip, instr);
+                }
+                if let Some(Event::NeedInput) | Some(Event::Halted(_)) = event {
+                    self.ip = ip;
+                }
+                Ok(event)
+            }
+            Err(f) => {
+                self.ip = ip;
+                Err(f.at(ip, instr))
+            }
+        }
+    }
+
+    /// Run until the machine wants input it has not been given, produces an
+    /// output, or halts.
+    pub fn run_until_event(&mut self) -> Result<Event, MachineError> {
+        loop {
+            if let Some(event) = self.step_event()? {
+                return Ok(event);
+            }
+        }
+    }
+
+    /// Queue a value for the next input instruction.
+    pub fn provide_input(&mut self, val: i64) {
+        self.inputs.push_back(val)
+    }
+
+    /// Take the next value queued with `provide_input`, if there is one.
+    pub fn take_input(&mut self) -> Option<i64> {
+        self.inputs.pop_front()
+    }
+
+    fn exec(&mut self, ip: usize, instr: i64) -> Result<Option<Event>, Fault> {
+        if self.arithmetic == Arithmetic::Big && self.data.get_exact(ip).is_some() {
+            return Err(Fault::BadOpcode);
+        }
+        let decoded = match self.decoder {
+            Decoder::Parse => parse_instr(instr)?,
+            Decoder::Table => decode::decode_word(instr)?,
+            Decoder::Cached => self.cache.get(ip, instr)?,
+        };
+        if let Some(tracker) = self.selfmod.as_mut() {
+            tracker.executed(ip, decoded.width());
+        }
+        self.ip += 1;
+        match decoded {
+            Instr::NoneArg(OpNone::Exit) => return Ok(Some(Event::Halted(self.get(0)?))),
             Instr::OneArg(arg, mode1) => match arg {
                 OpOne::Input => {
-                    let num = (self.input_callback)();
-                    self.fetch_and_set(mode1, num);
+                    let num = match self.inputs.front() {
+                        Some(&num) => num,
+                        None => return Ok(Some(Event::NeedInput)),
+                    };
+                    self.fetch_and_set(mode1, num)?;
+                    self.inputs.pop_front();
                 }
                 OpOne::Output => {
-                    let out = self.fetch(mode1);
-                    (self.output_callback)(out)
+                    let out = if self.arithmetic == Arithmetic::Big {
+                        let exact = self.fetch_big(mode1)?;
+                        let out = exact.wrapped();
+                        self.exact_output = Some(exact);
+                        out
+                    } else {
+                        self.fetch(mode1)?
+                    };
+                    return Ok(Some(Event::Output(out)));
                 }
                 OpOne::AdjustRelativeBase => {
-                    let adj = self.fetch(mode1);
-                    self.relative_base += adj;
+                    let adj = self.fetch(mode1)?;
+                    self.relative_base = self.arithmetic.add(self.relative_base, adj)?;
                 }
             },
             Instr::TwoArg(arg, (mode1, mode2)) => {
-                let v1 = self.fetch(mode1);
-                let v2 = self.fetch(mode2);
-                match arg {
-                    OpTwo::JumpIfTrue => {
-                        if v1 != 0 {
-                            self.ip = v2 as usize;
-                        }
-                    }
-                    OpTwo::JumpIfFalse => {
-                        if v1 == 0 {
-                            self.ip = v2 as usize;
-                        }
-                    }
+                let zero = match self.arithmetic {
+                    Arithmetic::Big => self.fetch_big(mode1)?.is_zero(),
+                    _ => self.fetch(mode1)? == 0,
+                };
+                let v2 = self.fetch(mode2)?;
+                let jump = match arg {
+                    OpTwo::JumpIfTrue => !zero,
+                    OpTwo::JumpIfFalse => zero,
+                };
+                if jump {
+                    self.ip = address(v2)?;
                 }
             }
+            Instr::ThreeArg(arg, (mode1, mode2, mode3)) if self.arithmetic == Arithmetic::Big => {
+                let v1 = self.fetch_big(mode1)?;
+                let v2 = self.fetch_big(mode2)?;
+                let result = match arg {
+                    OpThree::Add => &v1 + &v2,
+                    OpThree::Mul => &v1 * &v2,
+                    OpThree::LessThan => BigInt::from((v1 < v2) as i64),
+                    OpThree::Equals => BigInt::from((v1 == v2) as i64),
+                };
+                let addr = self.fetch_and_set(mode3, result.wrapped())?;
+                self.data.set_big(addr, result)?;
+            }
             Instr::ThreeArg(arg, (mode1, mode2, mode3)) => {
-                let v1 = self.fetch(mode1);
-                let v2 = self.fetch(mode2);
+                let v1 = self.fetch(mode1)?;
+                let v2 = self.fetch(mode2)?;
                 match arg {
                     OpThree::Add => {
-                        self.fetch_and_set(mode3, v1 + v2);
+                        let sum = self.arithmetic.add(v1, v2)?;
+                        self.fetch_and_set(mode3, sum)?
                     }
                     OpThree::Mul => {
-                        self.fetch_and_set(mode3, v1 * v2);
+                        let product = self.arithmetic.mul(v1, v2)?;
+                        self.fetch_and_set(mode3, product)?
                     }
-                    OpThree::LessThan => self.fetch_and_set(mode3, if v1 < v2 { 1 } else { 0 }),
-                    OpThree::Equals => self.fetch_and_set(mode3, if v1 == v2 { 1 } else { 0 }),
-                }
+                    OpThree::LessThan => self.fetch_and_set(mode3, (v1 < v2) as i64)?,
+                    OpThree::Equals => self.fetch_and_set(mode3, (v1 == v2) as i64)?,
+                };
             }
         };
-        None
+        Ok(None)
+    }
+
+    fn fetch(&mut self, mode: Mode) -> Result<i64, Fault> {
+        let addr = self.operand(mode)?;
+        if self.arithmetic == Arithmetic::Big && self.data.get_exact(addr).is_some() {
+            return Err(Fault::Overflow);
+        }
+        self.read_operand(addr)
     }
 
-    fn fetch(&mut self, mode: Mode) -> i64 {
-        let param = self.data[self.ip];
+    /// Like `fetch`, but for values that may be too large for an `i64` under
+    /// `Arithmetic::Big`.
+    fn fetch_big(&mut self, mode: Mode) -> Result<BigInt, Fault> {
+        let addr = self.operand(mode)?;
+        let val = self.read_operand(addr)?;
+        Ok(match self.data.get_exact(addr) {
+            Some(exact) => exact.clone(),
+            None => val.into(),
+        })
+    }
+
+    /// Step over the next parameter, returning the address of the operand it
+    /// refers to; in immediate mode, that's the parameter itself.
+    fn operand(&mut self, mode: Mode) -> Result<usize, Fault> {
+        let ip = self.ip;
         self.ip += 1;
         match mode {
-            Mode::Immediate => param,
-            Mode::Position => self.data[param as usize],
-            Mode::Relative => self.data[(param + self.relative_base) as usize],
+            Mode::Immediate => Ok(ip),
+            Mode::Position => address(self.pointer(ip)?),
+            Mode::Relative => address(self.arithmetic.add(self.pointer(ip)?, self.relative_base)?),
         }
     }
 
-    fn fetch_and_set(&mut self, mode: Mode, val: i64) {
-        let ptr = self.data[self.ip];
-        match mode {
-            Mode::Immediate => unreachable!(),
-            Mode::Position => {
-                self.set(ptr as usize, val);
-            }
-            Mode::Relative => {
-                self.set((ptr + self.relative_base) as usize, val);
-            }
+    fn read_operand(&mut self, addr: usize) -> Result<i64, Fault> {
+        let val = self.get(addr)?;
+        self.effects.reads[self.effects.nreads] = val;
+        self.effects.nreads += 1;
+        Ok(val)
+    }
+
+    /// Read a parameter that will be used as an address.
+    fn pointer(&self, addr: usize) -> Result<i64, Fault> {
+        if self.arithmetic == Arithmetic::Big && self.data.get_exact(addr).is_some() {
+            return Err(Fault::Overflow);
+        }
+        self.get(addr)
+    }
+
+    /// Store `val` through the next parameter, returning the address written.
+    fn fetch_and_set(&mut self, mode: Mode, val: i64) -> Result<usize, Fault> {
+        let ptr = self.pointer(self.ip)?;
+        let addr = match mode {
+            Mode::Immediate => return Err(Fault::WriteInImmediateMode),
+            Mode::Position => address(ptr)?,
+            Mode::Relative => address(self.arithmetic.add(ptr, self.relative_base)?)?,
+        };
+        if let Some(tracker) = self.selfmod.as_mut() {
+            tracker.wrote(addr, self.data.get(addr)?, val);
         }
+        self.set(addr, val)?;
+        self.effects.write = Some((addr, val));
         self.ip += 1;
+        Ok(addr)
     }
 
-    fn set(&mut self, pos: usize, val: i64) {
-        self.data[pos] = val
+    fn set(&mut self, pos: usize, val: i64) -> Result<(), Fault> {
+        self.data.set(pos, val)?;
+        if self.decoder == Decoder::Cached {
+            self.cache.invalidate(pos);
+        }
+        Ok(())
     }
 
-    fn get(&self, posn: usize) -> i64 {
-        self.data[posn]
+    fn get(&self, posn: usize) -> Result<i64, Fault> {
+        self.data.get(posn)
     }
 
     pub fn run(&mut self) -> i64 {
+        match self.try_run() {
+            Ok(v) => v,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub fn try_run(&mut self) -> Result<i64, MachineError> {
         loop {
-            if let Some(v) = self.step() {
-                return v;
+            if let Some(v) = self.try_step()? {
+                return Ok(v);
             }
         }
     }
 }
 
-#[derive(Debug, Clone)]
-enum Instr {
+fn address(addr: i64) -> Result<usize, Fault> {
+    if addr < 0 {
+        Err(Fault::NegativeAddress(addr))
+    } else {
+        Ok(addr as usize)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::enum_variant_names)]
+pub enum Instr {
     NoneArg(OpNone),
     OneArg(OpOne, Mode),
     TwoArg(OpTwo, (Mode, Mode)),
     ThreeArg(OpThree, (Mode, Mode, Mode)),
 }
 
-#[derive(Debug, Clone, Copy)]
-enum OpNone {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpNone {
     Exit,
 }
 
-#[derive(Debug, Clone, Copy)]
-enum OpOne {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpOne {
     Input,
     Output,
-    AdjustRelativeBase
+    AdjustRelativeBase,
 }
 
-#[derive(Debug, Clone, Copy)]
-enum OpTwo {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpTwo {
     JumpIfTrue,
     JumpIfFalse,
 }
 
-#[derive(Debug, Clone, Copy)]
-enum OpThree {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpThree {
     Add,
     Mul,
     LessThan,
@@ -202,52 +634,128 @@ enum OpThree {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum Mode {
+pub enum Mode {
     Position,
     Immediate,
-    Relative
+    Relative,
+}
+
+impl Instr {
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Instr::NoneArg(OpNone::Exit) => "hlt",
+            Instr::OneArg(OpOne::Input, _) => "in",
+            Instr::OneArg(OpOne::Output, _) => "out",
+            Instr::OneArg(OpOne::AdjustRelativeBase, _) => "arb",
+            Instr::TwoArg(OpTwo::JumpIfTrue, _) => "jt",
+            Instr::TwoArg(OpTwo::JumpIfFalse, _) => "jf",
+            Instr::ThreeArg(OpThree::Add, _) => "add",
+            Instr::ThreeArg(OpThree::Mul, _) => "mul",
+            Instr::ThreeArg(OpThree::LessThan, _) => "lt",
+            Instr::ThreeArg(OpThree::Equals, _) => "eq",
+        }
+    }
+
+    /// Parameter modes, one per operand.
+    pub fn modes(&self) -> Vec<Mode> {
+        match *self {
+            Instr::NoneArg(_) => vec![],
+            Instr::OneArg(_, m1) => vec![m1],
+            Instr::TwoArg(_, (m1, m2)) => vec![m1, m2],
+            Instr::ThreeArg(_, (m1, m2, m3)) => vec![m1, m2, m3],
+        }
+    }
+
+    /// Number of memory words the instruction occupies, opcode included.
+    pub fn width(&self) -> usize {
+        self.modes().len() + 1
+    }
+
+    /// The instruction word this decodes from; the inverse of `decode`.
+    pub fn encode(&self) -> i64 {
+        let opcode = match self {
+            Instr::ThreeArg(OpThree::Add, _) => 1,
+            Instr::ThreeArg(OpThree::Mul, _) => 2,
+            Instr::OneArg(OpOne::Input, _) => 3,
+            Instr::OneArg(OpOne::Output, _) => 4,
+            Instr::TwoArg(OpTwo::JumpIfTrue, _) => 5,
+            Instr::TwoArg(OpTwo::JumpIfFalse, _) => 6,
+            Instr::ThreeArg(OpThree::LessThan, _) => 7,
+            Instr::ThreeArg(OpThree::Equals, _) => 8,
+            Instr::OneArg(OpOne::AdjustRelativeBase, _) => 9,
+            Instr::NoneArg(OpNone::Exit) => 99,
+        };
+        let modes = self.modes().iter().rev().fold(0, |acc, &mode| {
+            let digit = match mode {
+                Mode::Position => 0,
+                Mode::Immediate => 1,
+                Mode::Relative => 2,
+            };
+            acc * 10 + digit
+        });
+        opcode + modes * 100
+    }
+
+    /// Whether the instruction writes through an immediate-mode operand,
+    /// which always fails at runtime.
+    pub fn writes_immediate(&self) -> bool {
+        match *self {
+            Instr::OneArg(OpOne::Input, mode) | Instr::ThreeArg(_, (_, _, mode)) => {
+                mode == Mode::Immediate
+            }
+            _ => false,
+        }
+    }
 }
 
 impl TryFrom<u8> for Mode {
-    type Error = String;
+    type Error = u8;
     fn try_from(val: u8) -> Result<Self, Self::Error> {
         match val {
             0 => Ok(Mode::Position),
             1 => Ok(Mode::Immediate),
             2 => Ok(Mode::Relative),
-            other => Err(format!("Bad mode: {}", other)),
+            other => Err(other),
         }
     }
 }
 
-fn parse_instr(instr: i64) -> Result<Instr, String> {
-    let digits = digits(instr as u32);
+/// Decode an instruction word into its operation and parameter modes.
+pub fn decode(instr: i64) -> Option<Instr> {
+    decode::decode_word(instr).ok()
+}
+
+fn parse_instr(instr: i64) -> Result<Instr, Fault> {
+    if instr < 0 {
+        return Err(Fault::BadOpcode);
+    }
+    let digits = digits(instr as u64);
     let opcode = digits[0] + digits.get(1).cloned().unwrap_or(0) * 10;
     let modes = if digits.len() > 2 { &digits[2..] } else { &[] };
     let ok = match opcode {
-        1 => Instr::ThreeArg(OpThree::Add, Mode::three(modes)),
-        2 => Instr::ThreeArg(OpThree::Mul, Mode::three(modes)),
-        3 => Instr::OneArg(OpOne::Input, Mode::one(modes)),
-        4 => Instr::OneArg(OpOne::Output, Mode::one(modes)),
-        5 => Instr::TwoArg(OpTwo::JumpIfTrue, Mode::two(modes)),
-        6 => Instr::TwoArg(OpTwo::JumpIfFalse, Mode::two(modes)),
-        7 => Instr::ThreeArg(OpThree::LessThan, Mode::three(modes)),
-        8 => Instr::ThreeArg(OpThree::Equals, Mode::three(modes)),
-        9 => Instr::OneArg(OpOne::AdjustRelativeBase, Mode::one(modes)),
+        1 => Instr::ThreeArg(OpThree::Add, Mode::three(modes)?),
+        2 => Instr::ThreeArg(OpThree::Mul, Mode::three(modes)?),
+        3 => Instr::OneArg(OpOne::Input, Mode::one(modes)?),
+        4 => Instr::OneArg(OpOne::Output, Mode::one(modes)?),
+        5 => Instr::TwoArg(OpTwo::JumpIfTrue, Mode::two(modes)?),
+        6 => Instr::TwoArg(OpTwo::JumpIfFalse, Mode::two(modes)?),
+        7 => Instr::ThreeArg(OpThree::LessThan, Mode::three(modes)?),
+        8 => Instr::ThreeArg(OpThree::Equals, Mode::three(modes)?),
+        9 => Instr::OneArg(OpOne::AdjustRelativeBase, Mode::one(modes)?),
         99 => Instr::NoneArg({
-            Mode::none(modes);
+            Mode::none(modes)?;
             OpNone::Exit
         }),
-        other => return Err(format!("Bad opcode: {}", other)),
+        _ => return Err(Fault::BadOpcode),
     };
     Ok(ok)
 }
 
-fn digits(mut v: u32) -> Vec<u8> {
+fn digits(mut v: u64) -> Vec<u8> {
     let mut digits = Vec::new();
     loop {
         digits.push((v % 10) as u8);
-        v = v / 10;
+        v /= 10;
         if v == 0 {
             break;
         }
@@ -256,31 +764,146 @@ fn digits(mut v: u32) -> Vec<u8> {
 }
 
 impl Mode {
-    fn get(ix: usize, modes: &[u8]) -> Self {
+    fn get(ix: usize, modes: &[u8]) -> Result<Self, Fault> {
         modes
             .get(ix)
-            .map(|&v| Mode::try_from(v).unwrap())
-            .unwrap_or(Mode::Position)
+            .map(|&v| Mode::try_from(v).map_err(Fault::BadMode))
+            .unwrap_or(Ok(Mode::Position))
     }
 
-    fn none(modes: &[u8]) -> () {
-        assert_eq!(modes.len(), 0);
+    /// Any mode digits beyond the instruction's arity must be absent.
+    fn unused(arity: usize, modes: &[u8]) -> Result<(), Fault> {
+        match modes.iter().skip(arity).find(|&&m| m != 0) {
+            Some(&m) => Err(Fault::BadMode(m)),
+            None => Ok(()),
+        }
     }
-    fn one(modes: &[u8]) -> Mode {
-        assert!(modes.len() <= 1);
+
+    fn none(modes: &[u8]) -> Result<(), Fault> {
+        Mode::unused(0, modes)
+    }
+    fn one(modes: &[u8]) -> Result<Mode, Fault> {
+        Mode::unused(1, modes)?;
         Mode::get(0, modes)
     }
-    fn two(modes: &[u8]) -> (Mode, Mode) {
-        assert!(modes.len() <= 2);
-        let one = Mode::get(0, modes);
-        let two = Mode::get(1, modes);
-        (one, two)
+    fn two(modes: &[u8]) -> Result<(Mode, Mode), Fault> {
+        Mode::unused(2, modes)?;
+        let one = Mode::get(0, modes)?;
+        let two = Mode::get(1, modes)?;
+        Ok((one, two))
     }
-    fn three(modes: &[u8]) -> (Mode, Mode, Mode) {
-        assert!(modes.len() <= 3);
-        let one = Mode::get(0, modes);
-        let two = Mode::get(1, modes);
-        let three = Mode::get(2, modes);
-        (one, two, three)
+    fn three(modes: &[u8]) -> Result<(Mode, Mode, Mode), Fault> {
+        Mode::unused(3, modes)?;
+        let one = Mode::get(0, modes)?;
+        let two = Mode::get(1, modes)?;
+        let three = Mode::get(2, modes)?;
+        Ok((one, two, three))
     }
 }
+
+#[test]
+fn bad_opcode_reports_location() {
+    let mut machine = Machine::new(vec![1101, 1, 1, 5, 42, 0]);
+    let err = machine.try_run().unwrap_err();
+    assert_eq!(err, MachineError::BadOpcode { ip: 4, instr: 42 });
+}
+
+#[test]
+fn write_in_immediate_mode() {
+    let mut machine = Machine::new(vec![11101, 1, 1, 5, 99]);
+    let err = machine.try_run().unwrap_err();
+    assert_eq!(
+        err,
+        MachineError::WriteInImmediateMode {
+            ip: 0,
+            instr: 11101
+        }
+    );
+}
+
+#[test]
+fn input_exhausted() {
+    let mut machine = Machine::new_with_fallible_io(vec![3, 0, 99], || None, |_| ());
+    let err = machine.try_run().unwrap_err();
+    assert_eq!(err, MachineError::InputExhausted { ip: 0, instr: 3 });
+}
+
+#[test]
+fn negative_address() {
+    let mut machine = Machine::new(vec![4, -3, 99]);
+    let err = machine.try_run().unwrap_err();
+    assert_eq!(
+        err,
+        MachineError::NegativeAddress {
+            ip: 0,
+            instr: 4,
+            addr: -3
+        }
+    );
+}
+
+#[test]
+fn relative_base_overflow() {
+    let mut machine =
+        Machine::new(vec![109, i64::MAX, 109, 1, 99]).with_arithmetic(Arithmetic::Checked);
+    let err = machine.try_run().unwrap_err();
+    assert_eq!(err, MachineError::Overflow { ip: 2, instr: 109 });
+}
+
+#[test]
+fn arithmetic_overflow_policy() {
+    // Square the largest value day 9 prints, then output it.
+    let program = vec![
+        1002,
+        9,
+        1125899906842624,
+        9,
+        4,
+        9,
+        99,
+        0,
+        0,
+        1125899906842624,
+    ];
+    let mut machine = Machine::new(program.clone());
+    assert_eq!(machine.run_until_event(), Ok(Event::Output(0)));
+
+    let mut machine = Machine::new(program).with_arithmetic(Arithmetic::Checked);
+    assert_eq!(
+        machine.run_until_event(),
+        Err(MachineError::Overflow { ip: 0, instr: 1002 })
+    );
+}
+
+#[test]
+fn memory_grows_and_is_limited() {
+    // Write the input to address 100000, read it back, and output it.
+    let program = vec![3, 100000, 4, 100000, 99];
+    let mut machine = Machine::new(program.clone());
+    machine.provide_input(7);
+    assert_eq!(machine.run_until_event(), Ok(Event::Output(7)));
+    assert_eq!(machine.state(), &program[..]);
+
+    let mut machine = Machine::new(program).with_memory_limit(1024);
+    machine.provide_input(7);
+    assert_eq!(
+        machine.run_until_event(),
+        Err(MachineError::AddressOutOfBounds {
+            ip: 0,
+            instr: 3,
+            addr: 100000
+        })
+    );
+}
+
+#[test]
+fn run_until_event_pauses_for_io() {
+    // Echo the input plus one, then halt.
+    let mut machine = Machine::new(vec![3, 9, 1001, 9, 1, 9, 4, 9, 99, 0]);
+    assert_eq!(machine.run_until_event(), Ok(Event::NeedInput));
+    assert_eq!(machine.run_until_event(), Ok(Event::NeedInput));
+    machine.provide_input(41);
+    assert_eq!(machine.run_until_event(), Ok(Event::Output(42)));
+    assert_eq!(machine.run_until_event(), Ok(Event::Halted(3)));
+    assert_eq!(machine.run_until_event(), Ok(Event::Halted(3)));
+}
@@ -0,0 +1,196 @@
+//! Stopping a run early: after a number of instructions, at a deadline, or
+//! when another thread asks.
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::{Machine, MachineError};
+
+/// How many instructions run between looks at the clock.
+const CLOCK_INTERVAL: u64 = 1024;
+
+/// A flag another thread can set to stop a running machine. Clones share
+/// the flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    max_steps: Option<u64>,
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    cancel: Option<CancelToken>,
+}
+
+impl Limits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stop after executing `steps` instructions.
+    pub fn with_max_steps(mut self, steps: u64) -> Self {
+        self.max_steps = Some(steps);
+        self
+    }
+
+    /// Stop once `timeout` has passed since the run started.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Stop once the clock passes `deadline`.
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Stop when `token` is cancelled.
+    pub fn with_cancel(mut self, token: CancelToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    StepLimit,
+    Deadline,
+    Cancelled,
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            StopReason::StepLimit => "Instruction limit reached",
+            StopReason::Deadline => "Deadline passed",
+            StopReason::Cancelled => "Cancelled",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+/// How a limited run ended, if not with an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// The machine halted; the value at address 0.
+    Halted(i64),
+    /// A limit stopped the machine before the instruction at `ip`, after
+    /// `steps` instructions. Running it again carries on from there.
+    Stopped {
+        reason: StopReason,
+        ip: usize,
+        steps: u64,
+    },
+}
+
+impl Machine {
+    /// Like `try_run`, but give up when any of `limits` is reached. The clock
+    /// is only checked every thousand or so instructions, so a deadline may
+    /// be overshot slightly.
+    pub fn run_with_limits(&mut self, limits: &Limits) -> Result<Outcome, MachineError> {
+        let started = Instant::now();
+        // A timeout too long for the clock to represent never expires.
+        let timeout = limits.timeout.and_then(|t| started.checked_add(t));
+        let deadline = match (limits.deadline, timeout) {
+            (Some(deadline), Some(timeout)) => Some(deadline.min(timeout)),
+            (deadline, timeout) => deadline.or(timeout),
+        };
+        let mut steps = 0;
+        loop {
+            let reason = if limits.max_steps.is_some_and(|max| steps >= max) {
+                Some(StopReason::StepLimit)
+            } else if limits.cancel.as_ref().is_some_and(|c| c.is_cancelled()) {
+                Some(StopReason::Cancelled)
+            } else if steps % CLOCK_INTERVAL == 0
+                && deadline.is_some_and(|deadline| Instant::now() >= deadline)
+            {
+                Some(StopReason::Deadline)
+            } else {
+                None
+            };
+            if let Some(reason) = reason {
+                let ip = self.ip;
+                return Ok(Outcome::Stopped { reason, ip, steps });
+            }
+            if let Some(v) = self.try_step()? {
+                return Ok(Outcome::Halted(v));
+            }
+            steps += 1;
+        }
+    }
+}
+
+#[test]
+fn step_limit_stops_infinite_loop() {
+    let mut machine = Machine::new(vec![1105, 1, 0]);
+    let outcome = machine.run_with_limits(&Limits::new().with_max_steps(10));
+    assert_eq!(
+        outcome,
+        Ok(Outcome::Stopped {
+            reason: StopReason::StepLimit,
+            ip: 0,
+            steps: 10
+        })
+    );
+
+    let mut machine = Machine::new(vec![1101, 2, 3, 0, 99]);
+    let outcome = machine.run_with_limits(&Limits::new().with_max_steps(10));
+    assert_eq!(outcome, Ok(Outcome::Halted(5)));
+
+    let mut machine = Machine::new(vec![1105, 1, 0]);
+    let limits = Limits::new().with_max_steps(10).with_timeout(Duration::MAX);
+    let outcome = machine.run_with_limits(&limits);
+    assert!(matches!(
+        outcome,
+        Ok(Outcome::Stopped {
+            reason: StopReason::StepLimit,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn deadline_and_cancel() {
+    let mut machine = Machine::new(vec![1105, 1, 0]);
+    let limits = Limits::new().with_timeout(Duration::from_millis(20));
+    let outcome = machine.run_with_limits(&limits).unwrap();
+    assert!(matches!(
+        outcome,
+        Outcome::Stopped {
+            reason: StopReason::Deadline,
+            ..
+        }
+    ));
+
+    let token = CancelToken::new();
+    let limits = Limits::new().with_cancel(token.clone());
+    let handle = std::thread::spawn(move || machine.run_with_limits(&limits));
+    std::thread::sleep(Duration::from_millis(10));
+    token.cancel();
+    let outcome = handle.join().unwrap().unwrap();
+    assert!(matches!(
+        outcome,
+        Outcome::Stopped {
+            reason: StopReason::Cancelled,
+            ip: 0,
+            ..
+        }
+    ));
+}
@@ -0,0 +1,170 @@
+//! Reading programs from text.
+//!
+//! Values are separated by commas, whitespace or both, so a program may be
+//! split across lines. A trailing comma is allowed and `#` starts a comment
+//! running to the end of the line.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::Machine;
+
+/// Where and why a program failed to parse. Lines and columns count from 1,
+/// tokens (the values in the program) from 0.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub token: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} (line {}, column {}, value {})",
+            self.message, self.line, self.column, self.token
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(io::Error),
+    Parse(ParseError),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Io(e) => write!(f, "{}", e),
+            LoadError::Parse(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Io(e) => Some(e),
+            LoadError::Parse(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(e: io::Error) -> Self {
+        LoadError::Io(e)
+    }
+}
+
+impl From<ParseError> for LoadError {
+    fn from(e: ParseError) -> Self {
+        LoadError::Parse(e)
+    }
+}
+
+pub fn parse_program(text: &str) -> Result<Vec<i64>, ParseError> {
+    let mut values = Vec::new();
+    // Whether the last separator seen was a comma, so that another comma
+    // before a value means a value is missing.
+    let mut after_comma = true;
+    for (line_ix, line) in text.lines().enumerate() {
+        let line = line.split('#').next().unwrap();
+        let mut chars = line.char_indices().peekable();
+        let mut column = 0;
+        while let Some((start, c)) = chars.next() {
+            column += 1;
+            if c.is_whitespace() {
+                continue;
+            }
+            if c == ',' {
+                if after_comma {
+                    return Err(ParseError {
+                        line: line_ix + 1,
+                        column,
+                        token: values.len(),
+                        message: "Missing value before ','".to_string(),
+                    });
+                }
+                after_comma = true;
+                continue;
+            }
+            let mut end = start + c.len_utf8();
+            let start_column = column;
+            while let Some(&(ix, c)) = chars.peek() {
+                if c == ',' || c.is_whitespace() {
+                    break;
+                }
+                chars.next();
+                column += 1;
+                end = ix + c.len_utf8();
+            }
+            let word = &line[start..end];
+            let val = word.parse().map_err(|_| ParseError {
+                line: line_ix + 1,
+                column: start_column,
+                token: values.len(),
+                message: format!("Bad value '{}'", word),
+            })?;
+            values.push(val);
+            after_comma = false;
+        }
+    }
+    Ok(values)
+}
+
+pub fn read_program(mut reader: impl Read) -> Result<Vec<i64>, LoadError> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text)?;
+    Ok(parse_program(&text)?)
+}
+
+pub fn load_program(path: impl AsRef<Path>) -> Result<Vec<i64>, LoadError> {
+    read_program(File::open(path)?)
+}
+
+impl Machine {
+    /// Load a program from a file, reporting where it is malformed.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, LoadError> {
+        Ok(Self::new(load_program(path)?))
+    }
+}
+
+#[test]
+fn accepts_loose_syntax() {
+    let text = "# header\n1,0, 0,\n  3 # add\n\n99,\n";
+    assert_eq!(parse_program(text), Ok(vec![1, 0, 0, 3, 99]));
+    assert_eq!(parse_program(""), Ok(vec![]));
+    assert_eq!(parse_program("1 2\t-3"), Ok(vec![1, 2, -3]));
+}
+
+#[test]
+fn reports_error_locations() {
+    assert_eq!(
+        parse_program("1,2,\n3, x4,5"),
+        Err(ParseError {
+            line: 2,
+            column: 4,
+            token: 3,
+            message: "Bad value 'x4'".to_string(),
+        })
+    );
+    let err = parse_program("1,,2").unwrap_err();
+    assert_eq!((err.line, err.column, err.token), (1, 3, 1));
+    assert_eq!(
+        err.to_string(),
+        "Missing value before ',' (line 1, column 3, value 1)"
+    );
+    assert!(matches!(
+        load_program("data/no-such-program"),
+        Err(LoadError::Io(_))
+    ));
+    assert_eq!(read_program("1,2,99".as_bytes()).unwrap(), vec![1, 2, 99]);
+    assert_eq!(load_program("data/02").unwrap()[0], 1);
+}
@@ -0,0 +1,170 @@
+use std::collections::BTreeMap;
+
+use crate::bigint::BigInt;
+use crate::Fault;
+
+/// Addresses below this are stored in a flat vector that grows on demand;
+/// anything higher lives in a sparse map so a stray write to a huge address
+/// doesn't allocate gigabytes.
+const DENSE_LIMIT: usize = 1 << 20;
+
+/// Default upper bound on addressable memory.
+const DEFAULT_LIMIT: usize = 1 << 32;
+
+/// Intcode memory. Every address below `limit` reads as zero until written;
+/// touching an address at or above `limit` is an error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Memory {
+    dense: Vec<i64>,
+    sparse: BTreeMap<usize, i64>,
+    /// Exact values of cells written under `Arithmetic::Big` that don't fit
+    /// in an `i64`. The cells themselves hold the low 64 bits.
+    exact: BTreeMap<usize, BigInt>,
+    limit: usize,
+}
+
+impl Memory {
+    pub fn new(program: Vec<i64>) -> Self {
+        Self {
+            dense: program,
+            sparse: BTreeMap::new(),
+            exact: BTreeMap::new(),
+            limit: DEFAULT_LIMIT,
+        }
+    }
+
+    /// Rebuild memory from `dense` and `sparse`. Fails if a sparse cell lies
+    /// inside the dense region, where it would be shadowed.
+    pub fn from_parts(
+        dense: Vec<i64>,
+        sparse: BTreeMap<usize, i64>,
+        limit: usize,
+    ) -> Result<Self, String> {
+        if let Some(&addr) = sparse.keys().next().filter(|&&addr| addr < dense.len()) {
+            return Err(format!(
+                "sparse cell {} lies inside dense memory of {} cells",
+                addr,
+                dense.len()
+            ));
+        }
+        Ok(Self {
+            dense,
+            sparse,
+            exact: BTreeMap::new(),
+            limit,
+        })
+    }
+
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// The contiguous low region of memory, which always covers the program.
+    pub fn dense(&self) -> &[i64] {
+        &self.dense
+    }
+
+    /// Cells above the dense region that have been written to.
+    pub fn sparse(&self) -> &BTreeMap<usize, i64> {
+        &self.sparse
+    }
+
+    /// Cells holding values too large for an `i64`; see `set_big`.
+    pub fn exact(&self) -> &BTreeMap<usize, BigInt> {
+        &self.exact
+    }
+
+    /// The exact value at `addr`, if it doesn't fit in an `i64`.
+    pub fn get_exact(&self, addr: usize) -> Option<&BigInt> {
+        self.exact.get(&addr)
+    }
+
+    pub fn get(&self, addr: usize) -> Result<i64, Fault> {
+        self.check(addr)?;
+        let val = match self.dense.get(addr) {
+            Some(&val) => val,
+            None => self.sparse.get(&addr).cloned().unwrap_or(0),
+        };
+        Ok(val)
+    }
+
+    pub fn set(&mut self, addr: usize, val: i64) -> Result<(), Fault> {
+        self.check(addr)?;
+        if addr < self.dense.len() {
+            self.dense[addr] = val;
+        } else if addr < DENSE_LIMIT {
+            self.dense.resize(addr + 1, 0);
+            self.dense[addr] = val;
+        } else {
+            self.sparse.insert(addr, val);
+        }
+        self.exact.remove(&addr);
+        Ok(())
+    }
+
+    /// Store a value of any size. One that doesn't fit in an `i64` leaves
+    /// its low 64 bits in the cell, which is what `get` returns.
+    pub fn set_big(&mut self, addr: usize, val: BigInt) -> Result<(), Fault> {
+        self.set(addr, val.wrapped())?;
+        if val.to_i64().is_none() {
+            self.exact.insert(addr, val);
+        }
+        Ok(())
+    }
+
+    fn check(&self, addr: usize) -> Result<(), Fault> {
+        if addr >= self.limit {
+            Err(Fault::AddressOutOfBounds(addr as i64))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[test]
+fn grows_on_demand() {
+    let mut mem = Memory::new(vec![1, 2, 3]);
+    assert_eq!(mem.get(1000).unwrap(), 0);
+    mem.set(1000, 7).unwrap();
+    assert_eq!(mem.get(1000).unwrap(), 7);
+    assert_eq!(mem.dense().len(), 1001);
+    mem.set(1 << 30, 9).unwrap();
+    assert_eq!(mem.get(1 << 30).unwrap(), 9);
+    assert_eq!(mem.dense().len(), 1001);
+}
+
+#[test]
+fn keeps_exact_values() {
+    let mut mem = Memory::new(vec![0; 4]);
+    let big: BigInt = "18446744073709551621".parse().unwrap();
+    mem.set_big(2, big.clone()).unwrap();
+    assert_eq!(mem.get(2).unwrap(), 5);
+    assert_eq!(mem.get_exact(2), Some(&big));
+    mem.set_big(3, BigInt::from(-3)).unwrap();
+    assert_eq!(mem.get_exact(3), None);
+    mem.set(2, 5).unwrap();
+    assert_eq!(mem.get_exact(2), None);
+}
+
+#[test]
+fn rejects_shadowed_sparse_cells() {
+    let sparse = |addr| std::iter::once((addr, 7)).collect();
+    assert!(Memory::from_parts(vec![1, 2, 3], sparse(3), 16).is_ok());
+    assert_eq!(
+        Memory::from_parts(vec![1, 2, 3], sparse(2), 16),
+        Err("sparse cell 2 lies inside dense memory of 3 cells".to_string())
+    );
+}
+
+#[test]
+fn respects_limit() {
+    let mut mem = Memory::new(vec![]);
+    mem.set_limit(16);
+    assert!(mem.set(15, 1).is_ok());
+    assert!(mem.set(16, 1).is_err());
+    assert!(mem.get(16).is_err());
+}
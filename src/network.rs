@@ -0,0 +1,682 @@
+//! Run several machines wired together, passing outputs along as inputs.
+//!
+//! ```no_run
+//! use advent2019::network::{Network, Topology};
+//! use advent2019::Machine;
+//!
+//! // Day 7's feedback loop: five amplifiers in a ring.
+//! let program = Machine::from_file("data/07").state().to_vec();
+//! let machines = [9, 8, 7, 6, 5]
+//!     .iter()
+//!     .map(|&phase| {
+//!         let mut m = Machine::new(program.clone());
+//!         m.provide_input(phase);
+//!         m
+//!     })
+//!     .collect();
+//! let mut network = Network::new(machines, Topology::Ring).unwrap();
+//! network.send(0, 0);
+//! let report = network.run().unwrap();
+//! println!("{:?}", report.outputs.last());
+//! ```
+
+use std::any::Any;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+use crate::schedule::{Policy, RoundRobin};
+use crate::{Event, Machine, MachineError};
+
+/// Somewhere a value can be sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Port {
+    /// The input queue of the machine at this index.
+    Machine(usize),
+    /// Out of the network, into `NetworkReport::outputs`.
+    Output,
+}
+
+/// How machine outputs are routed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Topology {
+    /// Each machine feeds the next; the last feeds `Port::Output`.
+    Chain,
+    /// Each machine feeds the next; the last feeds both the first machine
+    /// and `Port::Output`.
+    Ring,
+    /// Every other machine feeds `target`, which feeds `Port::Output`.
+    FanIn { target: usize },
+    /// `source` feeds every other machine, which all feed `Port::Output`.
+    FanOut { source: usize },
+    /// Machines emit packets of `width` words: a destination address
+    /// followed by `width - 1` values, which are delivered to that machine.
+    /// Addresses that aren't a machine go to `Port::Output`, address and all.
+    Packets { width: usize },
+    /// Explicit destinations for each machine's outputs, indexed by machine.
+    Custom(Vec<Vec<Port>>),
+}
+
+impl Topology {
+    /// Where machine `from`'s outputs go, for every topology but `Packets`.
+    fn ports(&self, from: usize, count: usize) -> Vec<Port> {
+        let last = count - 1;
+        match self {
+            Topology::Chain if from == last => vec![Port::Output],
+            Topology::Chain => vec![Port::Machine(from + 1)],
+            Topology::Ring if from == last => vec![Port::Machine(0), Port::Output],
+            Topology::Ring => vec![Port::Machine(from + 1)],
+            Topology::FanIn { target } if from == *target => vec![Port::Output],
+            Topology::FanIn { target } => vec![Port::Machine(*target)],
+            Topology::FanOut { source } if from == *source => (0..count)
+                .filter(|&ix| ix != from)
+                .map(Port::Machine)
+                .collect(),
+            Topology::FanOut { .. } => vec![Port::Output],
+            Topology::Packets { .. } => vec![],
+            Topology::Custom(ports) => ports.get(from).cloned().unwrap_or_default(),
+        }
+    }
+}
+
+/// Collects one machine's outputs and decides where they are delivered.
+struct Outbox {
+    ports: Vec<Port>,
+    packet_width: Option<usize>,
+    count: usize,
+    buffer: Vec<i64>,
+}
+
+impl Outbox {
+    fn new(topology: &Topology, from: usize, count: usize) -> Self {
+        let packet_width = match *topology {
+            Topology::Packets { width } => Some(width.max(1)),
+            _ => None,
+        };
+        Outbox {
+            ports: topology.ports(from, count),
+            packet_width,
+            count,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Accept one output value, returning any deliveries it completes.
+    fn push(&mut self, val: i64) -> Vec<(Port, Vec<i64>)> {
+        let width = match self.packet_width {
+            None => return self.ports.iter().map(|&port| (port, vec![val])).collect(),
+            Some(width) => width,
+        };
+        self.buffer.push(val);
+        if self.buffer.len() < width {
+            return vec![];
+        }
+        let packet = std::mem::take(&mut self.buffer);
+        match packet[0] {
+            addr if addr >= 0 && (addr as usize) < self.count => {
+                vec![(Port::Machine(addr as usize), packet[1..].to_vec())]
+            }
+            _ => vec![(Port::Output, packet)],
+        }
+    }
+}
+
+/// How a machine finished.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MachineStatus {
+    Halted(i64),
+    /// Waiting for input that will never arrive.
+    Blocked {
+        ip: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NetworkReport {
+    /// Every value sent to `Port::Output`. When running threaded, values
+    /// from different machines may interleave differently between runs.
+    pub outputs: Vec<i64>,
+    /// Final status of each machine, by index.
+    pub status: Vec<MachineStatus>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetworkError {
+    /// A machine failed; the rest of the network was abandoned.
+    Machine { index: usize, error: MachineError },
+    /// The thread running a machine panicked.
+    Panicked { index: usize, message: String },
+    /// Nothing can make progress: every machine has halted or is waiting for
+    /// input, no values are in flight, and these `(index, ip)` are waiting.
+    Deadlock { blocked: Vec<(usize, usize)> },
+    /// The topology refers to machine `index`, but there are only `count`.
+    NoSuchMachine { index: usize, count: usize },
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NetworkError::Machine { index, error } => write!(f, "Machine {}: {}", index, error),
+            NetworkError::Panicked { index, message } => {
+                write!(f, "Machine {} panicked: {}", index, message)
+            }
+            NetworkError::Deadlock { blocked } => {
+                let blocked: Vec<_> = blocked
+                    .iter()
+                    .map(|(index, ip)| format!("machine {} at ip {}", index, ip))
+                    .collect();
+                write!(f, "Deadlock: {} waiting for input", blocked.join(", "))
+            }
+            NetworkError::NoSuchMachine { index, count } => {
+                write!(f, "No machine {} in a network of {}", index, count)
+            }
+        }
+    }
+}
+
+impl std::error::Error for NetworkError {}
+
+/// Input queues shared between machine threads, which also notice when the
+/// network can no longer make progress.
+struct Mailboxes {
+    state: Mutex<MailboxState>,
+    changed: Condvar,
+}
+
+struct MailboxState {
+    queues: Vec<VecDeque<i64>>,
+    waiting: Vec<bool>,
+    finished: Vec<bool>,
+    /// Set once the network has settled or a machine has failed; waiting
+    /// machines give up.
+    stopped: bool,
+}
+
+impl MailboxState {
+    fn settled(&self) -> bool {
+        (0..self.queues.len())
+            .all(|ix| self.finished[ix] || (self.waiting[ix] && self.queues[ix].is_empty()))
+    }
+}
+
+impl Mailboxes {
+    fn new(count: usize) -> Self {
+        Mailboxes {
+            state: Mutex::new(MailboxState {
+                queues: vec![VecDeque::new(); count],
+                waiting: vec![false; count],
+                finished: vec![false; count],
+                stopped: false,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    fn send(&self, to: usize, vals: Vec<i64>) {
+        let mut state = self.state.lock().unwrap();
+        state.queues[to].extend(vals);
+        self.changed.notify_all();
+    }
+
+    /// Wait for a value for machine `ix`, or `None` if none will ever come.
+    fn recv(&self, ix: usize) -> Option<i64> {
+        let mut state = self.state.lock().unwrap();
+        state.waiting[ix] = true;
+        loop {
+            if let Some(val) = state.queues[ix].pop_front() {
+                state.waiting[ix] = false;
+                return Some(val);
+            }
+            if state.stopped || state.settled() {
+                state.stopped = true;
+                self.changed.notify_all();
+                return None;
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    /// Record that machine `ix` will neither send nor receive any more.
+    /// A failed machine stops the whole network.
+    fn finish(&self, ix: usize, failed: bool) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.finished[ix] = true;
+        state.stopped |= failed;
+        self.changed.notify_all();
+    }
+}
+
+/// Marks a machine finished when its thread ends, even by panicking.
+struct Finish<'a> {
+    mailboxes: &'a Mailboxes,
+    ix: usize,
+    failed: bool,
+}
+
+impl Drop for Finish<'_> {
+    fn drop(&mut self) {
+        self.mailboxes
+            .finish(self.ix, self.failed || thread::panicking());
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "unknown panic".to_string(),
+        },
+    }
+}
+
+/// Instructions a machine runs before `Network::run` picks the next one.
+pub const DEFAULT_SLICE: u64 = 1000;
+
+pub struct Network {
+    machines: Vec<Machine>,
+    topology: Topology,
+    deadlock_is_error: bool,
+    policy: Box<dyn Policy>,
+    slice: u64,
+}
+
+impl Network {
+    /// Fails with `NetworkError::NoSuchMachine` if the topology names a
+    /// machine index that isn't in `machines`.
+    pub fn new(machines: Vec<Machine>, topology: Topology) -> Result<Self, NetworkError> {
+        let count = machines.len();
+        let named: Vec<usize> = match &topology {
+            Topology::FanIn { target } => vec![*target],
+            Topology::FanOut { source } => vec![*source],
+            Topology::Custom(ports) => ports
+                .iter()
+                .flatten()
+                .filter_map(|port| match *port {
+                    Port::Machine(ix) => Some(ix),
+                    Port::Output => None,
+                })
+                .collect(),
+            Topology::Chain | Topology::Ring | Topology::Packets { .. } => vec![],
+        };
+        if let Some(&index) = named.iter().find(|&&ix| ix >= count) {
+            return Err(NetworkError::NoSuchMachine { index, count });
+        }
+        Ok(Network {
+            machines,
+            topology,
+            deadlock_is_error: false,
+            policy: Box::new(RoundRobin::default()),
+            slice: DEFAULT_SLICE,
+        })
+    }
+
+    /// Choose which machine runs next with `policy` instead of round robin.
+    /// Only affects `run`.
+    pub fn with_policy(mut self, policy: impl Policy + 'static) -> Self {
+        self.policy = Box::new(policy);
+        self
+    }
+
+    /// Let each machine execute at most `slice` instructions per turn, or
+    /// fewer if it halts or has to wait for input. Only affects `run`.
+    pub fn with_slice(mut self, slice: u64) -> Self {
+        assert!(slice > 0, "Slice must be at least one instruction");
+        self.slice = slice;
+        self
+    }
+
+    /// Fail with `NetworkError::Deadlock` if the network settles with any
+    /// machine still waiting for input, rather than reporting it as
+    /// `MachineStatus::Blocked`.
+    pub fn with_deadlock_errors(mut self) -> Self {
+        self.deadlock_is_error = true;
+        self
+    }
+
+    /// Queue a value for machine `index` before the network starts.
+    pub fn send(&mut self, index: usize, val: i64) {
+        self.machines[index].provide_input(val)
+    }
+
+    fn report(
+        &self,
+        outputs: Vec<i64>,
+        status: Vec<MachineStatus>,
+    ) -> Result<NetworkReport, NetworkError> {
+        let blocked: Vec<_> = status
+            .iter()
+            .enumerate()
+            .filter_map(|(ix, status)| match status {
+                MachineStatus::Blocked { ip } => Some((ix, *ip)),
+                MachineStatus::Halted(_) => None,
+            })
+            .collect();
+        if self.deadlock_is_error && !blocked.is_empty() {
+            return Err(NetworkError::Deadlock { blocked });
+        }
+        Ok(NetworkReport { outputs, status })
+    }
+
+    /// Run every machine on the current thread, a slice at a time as chosen
+    /// by the policy, until they have all halted or are waiting for input
+    /// nobody will send. The result is the same every time.
+    pub fn run(&mut self) -> Result<NetworkReport, NetworkError> {
+        let count = self.machines.len();
+        let mut outboxes: Vec<_> = (0..count)
+            .map(|ix| Outbox::new(&self.topology, ix, count))
+            .collect();
+        let mut outputs = Vec::new();
+        let mut status = vec![None; count];
+        loop {
+            let runnable: Vec<_> = (0..count)
+                .filter(|&ix| match status[ix] {
+                    None => true,
+                    Some(MachineStatus::Blocked { .. }) => !self.machines[ix].inputs.is_empty(),
+                    Some(MachineStatus::Halted(_)) => false,
+                })
+                .collect();
+            if runnable.is_empty() {
+                let status = status.into_iter().map(Option::unwrap).collect();
+                return self.report(outputs, status);
+            }
+            let ix = self.policy.next(&runnable);
+            assert!(
+                runnable.contains(&ix),
+                "Policy chose machine {}, which can't run",
+                ix
+            );
+            status[ix] = None;
+
+            for _ in 0..self.slice {
+                let event = self.machines[ix]
+                    .step_event()
+                    .map_err(|error| NetworkError::Machine { index: ix, error })?;
+                match event {
+                    None => {}
+                    Some(Event::Output(val)) => {
+                        for (port, vals) in outboxes[ix].push(val) {
+                            match port {
+                                Port::Machine(to) => self.machines[to].inputs.extend(vals),
+                                Port::Output => outputs.extend(vals),
+                            }
+                        }
+                    }
+                    Some(Event::NeedInput) => {
+                        let ip = self.machines[ix].ip();
+                        status[ix] = Some(MachineStatus::Blocked { ip });
+                        break;
+                    }
+                    Some(Event::Halted(val)) => {
+                        status[ix] = Some(MachineStatus::Halted(val));
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Run every machine on its own thread, until they have all halted or
+    /// are waiting for input nobody will send. A machine error or panic stops
+    /// the other machines the next time they wait for input.
+    pub fn run_threaded(&mut self) -> Result<NetworkReport, NetworkError> {
+        let count = self.machines.len();
+        let mailboxes = Mailboxes::new(count);
+        let outputs = Mutex::new(Vec::new());
+        let (mailboxes, outputs, topology) = (&mailboxes, &outputs, &self.topology);
+        let machines = &mut self.machines;
+
+        let results: Vec<_> = thread::scope(|scope| {
+            let handles: Vec<_> = machines
+                .iter_mut()
+                .enumerate()
+                .map(|(ix, machine)| {
+                    scope.spawn(move || {
+                        let mut finish = Finish {
+                            mailboxes,
+                            ix,
+                            failed: true,
+                        };
+                        let mut outbox = Outbox::new(topology, ix, count);
+                        let status = loop {
+                            let event = machine
+                                .run_until_event()
+                                .map_err(|error| NetworkError::Machine { index: ix, error })?;
+                            match event {
+                                Event::Output(val) => {
+                                    for (port, vals) in outbox.push(val) {
+                                        match port {
+                                            Port::Machine(to) => mailboxes.send(to, vals),
+                                            Port::Output => outputs.lock().unwrap().extend(vals),
+                                        }
+                                    }
+                                }
+                                Event::NeedInput => match mailboxes.recv(ix) {
+                                    Some(val) => machine.provide_input(val),
+                                    None => break MachineStatus::Blocked { ip: machine.ip() },
+                                },
+                                Event::Halted(val) => break MachineStatus::Halted(val),
+                            }
+                        };
+                        finish.failed = false;
+                        Ok(status)
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .enumerate()
+                .map(|(index, handle)| {
+                    handle.join().unwrap_or_else(|payload| {
+                        Err(NetworkError::Panicked {
+                            index,
+                            message: panic_message(payload),
+                        })
+                    })
+                })
+                .collect()
+        });
+
+        let status = results.into_iter().collect::<Result<_, _>>()?;
+        let outputs = outputs.lock().unwrap().clone();
+        self.report(outputs, status)
+    }
+}
+
+#[cfg(test)]
+fn machines(src: &str, inputs: &[&[i64]]) -> Vec<Machine> {
+    let program = crate::asm::assemble(src).unwrap();
+    inputs
+        .iter()
+        .map(|inputs| {
+            let mut m = Machine::new(program.clone());
+            for &val in inputs.iter() {
+                m.provide_input(val);
+            }
+            m
+        })
+        .collect()
+}
+
+#[cfg(test)]
+const ADD_ONE_FOREVER: &str = "
+    loop:   in x
+            add x, #1, x
+            out x
+            jt #1, #loop
+    x:      db 0
+";
+
+#[test]
+fn chain_and_ring() {
+    let mut network =
+        Network::new(machines(ADD_ONE_FOREVER, &[&[], &[], &[]]), Topology::Chain).unwrap();
+    network.send(0, 10);
+    let report = network.run().unwrap();
+    assert_eq!(report.outputs, vec![13]);
+    assert_eq!(report.status[0], MachineStatus::Blocked { ip: 0 });
+
+    // Add up the first machine's inputs, passing the total round a ring.
+    let adder = "
+        in x
+        in y
+        add x, y, x
+        out x
+        hlt
+        x: db 0
+        y: db 0
+    ";
+    let mut network = Network::new(machines(adder, &[&[1], &[2], &[3]]), Topology::Ring).unwrap();
+    network.send(0, 100);
+    let report = network.run().unwrap();
+    assert_eq!(report.outputs, vec![106]);
+    assert!(report
+        .status
+        .iter()
+        .all(|s| matches!(s, MachineStatus::Halted(_))));
+}
+
+#[test]
+fn fan_in_and_out() {
+    let mut network = Network::new(
+        machines(ADD_ONE_FOREVER, &[&[1], &[2], &[]]),
+        Topology::FanIn { target: 2 },
+    )
+    .unwrap();
+    assert_eq!(network.run().unwrap().outputs, vec![3, 4]);
+
+    let mut network = Network::new(
+        machines(ADD_ONE_FOREVER, &[&[1], &[], &[]]),
+        Topology::FanOut { source: 0 },
+    )
+    .unwrap();
+    assert_eq!(network.run().unwrap().outputs, vec![3, 3]);
+}
+
+#[test]
+fn packets() {
+    // Forward each input, plus one, to the machine named by the next input.
+    let forwarder = "
+        loop:   in x
+                in y
+                add y, #1, y
+                out x
+                out y
+                jt #1, #loop
+        x:      db 0
+        y:      db 0
+    ";
+    let mut network = Network::new(
+        machines(forwarder, &[&[1, 10], &[255], &[]]),
+        Topology::Packets { width: 2 },
+    )
+    .unwrap();
+    let report = network.run().unwrap();
+    assert_eq!(report.outputs, vec![255, 12]);
+}
+
+#[test]
+fn threaded_matches_cooperative() {
+    let build = || {
+        let mut network =
+            Network::new(machines(ADD_ONE_FOREVER, &[&[], &[], &[]]), Topology::Chain).unwrap();
+        network.send(0, 10);
+        network.send(0, 20);
+        network
+    };
+    let cooperative = build().run().unwrap();
+    let threaded = build().run_threaded().unwrap();
+    assert_eq!(cooperative, threaded);
+}
+
+#[test]
+fn detects_deadlock() {
+    // Nobody has any input, so everyone waits on everyone else forever.
+    let build = || {
+        Network::new(machines(ADD_ONE_FOREVER, &[&[], &[], &[]]), Topology::Ring)
+            .unwrap()
+            .with_deadlock_errors()
+    };
+    let expected = NetworkError::Deadlock {
+        blocked: vec![(0, 0), (1, 0), (2, 0)],
+    };
+    assert_eq!(build().run(), Err(expected.clone()));
+    assert_eq!(build().run_threaded(), Err(expected.clone()));
+    assert_eq!(
+        expected.to_string(),
+        "Deadlock: machine 0 at ip 0, machine 1 at ip 0, machine 2 at ip 0 waiting for input"
+    );
+}
+
+#[test]
+fn threaded_failures_are_errors() {
+    let mut panicky = machines(ADD_ONE_FOREVER, &[&[1], &[], &[]]);
+    panicky[1].set_tracer(|_: &crate::TraceEntry| panic!("boom"));
+    let mut network = Network::new(panicky, Topology::Ring).unwrap();
+    assert_eq!(
+        network.run_threaded(),
+        Err(NetworkError::Panicked {
+            index: 1,
+            message: "boom".to_string()
+        })
+    );
+
+    let mut failing = machines(ADD_ONE_FOREVER, &[&[1], &[], &[]]);
+    failing[2] = Machine::new(vec![3, 0, 77]);
+    let mut network = Network::new(failing, Topology::Ring).unwrap();
+    assert!(matches!(
+        network.run_threaded(),
+        Err(NetworkError::Machine { index: 2, .. })
+    ));
+}
+
+#[test]
+fn policies_decide_interleaving() {
+    // Output your own number three times.
+    let src = "
+                in id
+        loop:   out id
+                add n, #-1, n
+                jt n, #loop
+                hlt
+        id:     db 0
+        n:      db 3
+    ";
+    fn run(src: &str, policy: impl Policy + 'static) -> Vec<i64> {
+        let topology = Topology::Custom(vec![vec![Port::Output]; 3]);
+        let mut network = Network::new(machines(src, &[&[0], &[1], &[2]]), topology)
+            .unwrap()
+            .with_policy(policy)
+            .with_slice(3);
+        network.run().unwrap().outputs
+    }
+    assert_eq!(
+        run(src, RoundRobin::default()),
+        vec![0, 1, 2, 0, 1, 2, 0, 1, 2]
+    );
+    assert_eq!(
+        run(src, crate::schedule::Priority),
+        vec![0, 0, 0, 1, 1, 1, 2, 2, 2]
+    );
+    let shuffled = run(src, crate::schedule::Shuffle::new(7));
+    assert_eq!(shuffled, run(src, crate::schedule::Shuffle::new(7)));
+    assert_ne!(shuffled, run(src, RoundRobin::default()));
+}
+
+#[test]
+fn rejects_unknown_machines() {
+    let build = |topology| Network::new(machines(ADD_ONE_FOREVER, &[&[], &[]]), topology);
+    let expected = NetworkError::NoSuchMachine { index: 2, count: 2 };
+    assert_eq!(
+        build(Topology::FanIn { target: 2 }).err(),
+        Some(expected.clone())
+    );
+    assert_eq!(
+        build(Topology::FanOut { source: 2 }).err(),
+        Some(expected.clone())
+    );
+    let custom = Topology::Custom(vec![vec![Port::Machine(1)], vec![Port::Machine(2)]]);
+    assert_eq!(build(custom).err(), Some(expected.clone()));
+    assert_eq!(expected.to_string(), "No machine 2 in a network of 2");
+}
@@ -0,0 +1,225 @@
+//! Search phase settings for a chain of amplifiers, as in day 7.
+//!
+//! Every ordering of distinct phases is tried, in parallel, and the results
+//! ranked by the signal that comes out of the last amplifier.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::panic;
+use std::thread;
+
+use itertools::Itertools;
+
+use crate::network::{Network, NetworkError, Topology};
+use crate::Machine;
+
+/// Run one amplifier per phase, feeding 0 into the first, and return the last
+/// signal out of the last amplifier. With `feedback` the last amplifier also
+/// feeds the first until they halt. With no phases there is no signal.
+pub fn amplify(
+    program: &[i64],
+    phases: &[i64],
+    feedback: bool,
+) -> Result<Option<i64>, NetworkError> {
+    if phases.is_empty() {
+        return Ok(None);
+    }
+    let machines = phases
+        .iter()
+        .map(|&phase| {
+            let mut m = Machine::new(program.to_vec());
+            m.provide_input(phase);
+            m
+        })
+        .collect();
+    let topology = if feedback {
+        Topology::Ring
+    } else {
+        Topology::Chain
+    };
+    let mut network = Network::new(machines, topology)?;
+    network.send(0, 0);
+    Ok(network.run()?.outputs.last().cloned())
+}
+
+/// One phase setting and the signal it produced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    pub phases: Vec<i64>,
+    pub signal: i64,
+}
+
+/// Highest signal first; equal signals in phase order.
+fn rank(a: &Candidate, b: &Candidate) -> Ordering {
+    b.signal
+        .cmp(&a.signal)
+        .then_with(|| a.phases.cmp(&b.phases))
+}
+
+/// Every candidate that produced a signal, best first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ranking {
+    pub ranked: Vec<Candidate>,
+}
+
+impl Ranking {
+    pub fn best(&self) -> Option<&Candidate> {
+        self.ranked.first()
+    }
+}
+
+impl fmt::Display for Ranking {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (ix, candidate) in self.ranked.iter().enumerate() {
+            let phases: Vec<_> = candidate.phases.iter().map(|p| p.to_string()).collect();
+            writeln!(
+                f,
+                "{:>5}  {:<20} {}",
+                ix + 1,
+                phases.join(","),
+                candidate.signal
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub struct PhaseSearch {
+    program: Vec<i64>,
+    phases: Vec<i64>,
+    amplifiers: usize,
+    feedback: bool,
+    threads: usize,
+}
+
+impl PhaseSearch {
+    /// Try every way of giving `amplifiers` amplifiers distinct phases drawn
+    /// from `phases`.
+    pub fn new(
+        program: Vec<i64>,
+        phases: impl IntoIterator<Item = i64>,
+        amplifiers: usize,
+    ) -> Self {
+        let threads = thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+        PhaseSearch {
+            program,
+            phases: phases.into_iter().collect(),
+            amplifiers,
+            feedback: false,
+            threads,
+        }
+    }
+
+    /// Connect the last amplifier back to the first.
+    pub fn with_feedback(mut self, feedback: bool) -> Self {
+        self.feedback = feedback;
+        self
+    }
+
+    /// Evaluate on at most `threads` threads, rather than one per core.
+    pub fn with_threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Evaluate every candidate. Settings for which the amplifiers produce no
+    /// signal are left out; any machine error stops the search.
+    pub fn run(&self) -> Result<Ranking, NetworkError> {
+        let settings: Vec<Vec<i64>> = self
+            .phases
+            .iter()
+            .cloned()
+            .permutations(self.amplifiers)
+            .collect();
+        let chunk = settings.len().div_ceil(self.threads).max(1);
+
+        let results: Vec<Result<Vec<Candidate>, NetworkError>> = thread::scope(|scope| {
+            let handles: Vec<_> = settings
+                .chunks(chunk)
+                .map(|settings| {
+                    scope.spawn(move || {
+                        let mut candidates = Vec::new();
+                        for phases in settings {
+                            if let Some(signal) = amplify(&self.program, phases, self.feedback)? {
+                                candidates.push(Candidate {
+                                    phases: phases.clone(),
+                                    signal,
+                                });
+                            }
+                        }
+                        Ok(candidates)
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                // Machine failures come back as errors, so a panic here is a
+                // bug; pass it on to the caller with its original message.
+                .map(|handle| handle.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+                .collect()
+        });
+
+        let mut ranked = Vec::with_capacity(settings.len());
+        for candidates in results {
+            ranked.extend(candidates?);
+        }
+        ranked.sort_by(rank);
+        Ok(Ranking { ranked })
+    }
+}
+
+#[test]
+fn ranks_every_setting() {
+    // Output (input * 10 + phase): the signal spells out the phases.
+    let program = crate::asm::assemble(
+        "
+        in phase
+        in signal
+        mul signal, #10, signal
+        add signal, phase, signal
+        out signal
+        hlt
+        phase:  db 0
+        signal: db 0
+        ",
+    )
+    .unwrap();
+    let serial = PhaseSearch::new(program.clone(), 1..=3, 2)
+        .with_threads(1)
+        .run()
+        .unwrap();
+    let parallel = PhaseSearch::new(program, 1..=3, 2)
+        .with_threads(4)
+        .run()
+        .unwrap();
+    assert_eq!(serial, parallel);
+    let signals: Vec<_> = serial.ranked.iter().map(|c| c.signal).collect();
+    assert_eq!(signals, vec![32, 31, 23, 21, 13, 12]);
+    assert_eq!(serial.best().unwrap().phases, vec![3, 2]);
+    assert!(serial.to_string().starts_with("    1  3,2"));
+}
+
+#[test]
+fn no_amplifiers_no_signal() {
+    let program = vec![3, 9, 3, 9, 4, 9, 99, 0, 0, 0];
+    assert_eq!(amplify(&program, &[], false), Ok(None));
+    assert_eq!(amplify(&program, &[], true), Ok(None));
+    let ranking = PhaseSearch::new(program, 0..3, 0).run().unwrap();
+    assert!(ranking.ranked.is_empty());
+}
+
+#[test]
+fn ties_break_by_phases() {
+    // Ignore the phase and echo the signal, so every setting ties.
+    let program = vec![3, 9, 3, 9, 4, 9, 99, 0, 0, 0];
+    let ranking = PhaseSearch::new(program, 0..3, 3)
+        .with_feedback(false)
+        .run()
+        .unwrap();
+    assert_eq!(ranking.ranked.len(), 6);
+    assert!(ranking.ranked.iter().all(|c| c.signal == 0));
+    assert_eq!(ranking.best().unwrap().phases, vec![0, 1, 2]);
+    assert_eq!(ranking.ranked[5].phases, vec![2, 1, 0]);
+}
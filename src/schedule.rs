@@ -0,0 +1,91 @@
+//! Policies deciding which machine runs next when a `Network` shares one
+//! thread between many machines.
+//!
+//! A policy only ever sees which machines are able to run, so given the same
+//! machines and the same policy a network always interleaves them the same
+//! way.
+
+use crate::rng::Rng;
+
+/// Chooses the next machine to run a slice.
+pub trait Policy {
+    /// Pick one of `runnable`, a non-empty list of machine indices in
+    /// increasing order.
+    fn next(&mut self, runnable: &[usize]) -> usize;
+}
+
+impl<F: FnMut(&[usize]) -> usize> Policy for F {
+    fn next(&mut self, runnable: &[usize]) -> usize {
+        self(runnable)
+    }
+}
+
+/// Take turns in index order, skipping machines that can't run.
+#[derive(Debug, Clone, Default)]
+pub struct RoundRobin {
+    last: Option<usize>,
+}
+
+impl Policy for RoundRobin {
+    fn next(&mut self, runnable: &[usize]) -> usize {
+        let ix = match self.last {
+            Some(last) => runnable.iter().find(|&&ix| ix > last),
+            None => None,
+        };
+        let ix = *ix.unwrap_or(&runnable[0]);
+        self.last = Some(ix);
+        ix
+    }
+}
+
+/// Always run the lowest-numbered machine that can run.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Priority;
+
+impl Policy for Priority {
+    fn next(&mut self, runnable: &[usize]) -> usize {
+        runnable[0]
+    }
+}
+
+/// Pick at random, from a seed, so a "random" interleaving can be replayed.
+#[derive(Debug, Clone)]
+pub struct Shuffle {
+    rng: Rng,
+}
+
+impl Shuffle {
+    pub fn new(seed: u64) -> Self {
+        Shuffle {
+            rng: Rng::new(seed),
+        }
+    }
+}
+
+impl Policy for Shuffle {
+    fn next(&mut self, runnable: &[usize]) -> usize {
+        runnable[self.rng.below(runnable.len())]
+    }
+}
+
+#[test]
+fn round_robin_skips_and_wraps() {
+    let mut policy = RoundRobin::default();
+    let picks: Vec<_> = [&[0, 1, 2][..], &[0, 1, 2], &[0, 2], &[0, 1], &[1]]
+        .iter()
+        .map(|runnable| policy.next(runnable))
+        .collect();
+    assert_eq!(picks, vec![0, 1, 2, 0, 1]);
+}
+
+#[test]
+fn shuffle_is_reproducible() {
+    let picks = |seed| {
+        let mut policy = Shuffle::new(seed);
+        (0..20)
+            .map(|_| policy.next(&[0, 1, 2, 3]))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(picks(1), picks(1));
+    assert_ne!(picks(1), picks(2));
+}
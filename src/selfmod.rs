@@ -0,0 +1,165 @@
+//! Detecting programs that rewrite their own code.
+//!
+//! Once tracking is switched on, the machine remembers every address that
+//! has been executed as part of an instruction, opcode or operand, and
+//! records any later write that changes one of them. Writes to code that
+//! has not run yet, like day 5 filling in the word at 6 before reaching it,
+//! are how programs are meant to be built and are not reported.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::Machine;
+
+/// A write that changed an instruction after it had been executed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SelfModification {
+    /// The instruction that did the writing.
+    pub ip: usize,
+    /// The address written to.
+    pub addr: usize,
+    pub old: i64,
+    pub new: i64,
+}
+
+impl fmt::Display for SelfModification {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ip {} wrote {}: {} -> {}",
+            self.ip, self.addr, self.old, self.new
+        )
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SelfModTracker {
+    /// Every address executed so far, kept sparse since code may run
+    /// anywhere in memory.
+    executed: HashSet<usize>,
+    /// The instruction currently executing.
+    ip: usize,
+    sites: Vec<SelfModification>,
+    /// Every address in `sites`.
+    modified: HashSet<usize>,
+}
+
+impl SelfModTracker {
+    /// Note that the `width` words at `ip` are being executed.
+    pub fn executed(&mut self, ip: usize, width: usize) {
+        self.executed.extend(ip..ip + width);
+        self.ip = ip;
+    }
+
+    pub fn wrote(&mut self, addr: usize, old: i64, new: i64) {
+        if old != new && self.executed.contains(&addr) {
+            self.sites.push(SelfModification {
+                ip: self.ip,
+                addr,
+                old,
+                new,
+            });
+            self.modified.insert(addr);
+        }
+    }
+}
+
+impl Machine {
+    /// Start recording writes to instructions that have already run. See
+    /// `self_modifications`. Restoring a snapshot starts the record afresh.
+    pub fn track_self_modification(&mut self) {
+        if self.selfmod.is_none() {
+            self.selfmod = Some(Box::default());
+        }
+    }
+
+    pub fn with_self_modification_tracking(mut self) -> Self {
+        self.track_self_modification();
+        self
+    }
+
+    /// Every write so far that changed an already executed instruction, in
+    /// the order they happened. Empty unless tracking is on.
+    pub fn self_modifications(&self) -> &[SelfModification] {
+        match &self.selfmod {
+            Some(tracker) => &tracker.sites,
+            None => &[],
+        }
+    }
+
+    /// Whether the instruction words at `addr` are known to be unchanged
+    /// since they were executed, e.g. so a decoded copy can be reused.
+    /// Always false unless tracking is on.
+    pub fn is_unmodified_code(&self, addr: usize) -> bool {
+        match &self.selfmod {
+            Some(tracker) => tracker.executed.contains(&addr) && !tracker.modified.contains(&addr),
+            None => false,
+        }
+    }
+}
+
+#[test]
+fn reports_writes_to_executed_code() {
+    // add 1, #1, 1 rewrites its own first operand; then mul 4, #3, 4 turns
+    // the word after it into hlt before it runs, which is not reported.
+    let mut machine =
+        Machine::new(vec![1001, 1, 1, 1, 1002, 8, 3, 8, 33]).with_self_modification_tracking();
+    assert_eq!(machine.run(), 1001);
+    assert_eq!(
+        machine.self_modifications(),
+        &[SelfModification {
+            ip: 0,
+            addr: 1,
+            old: 1,
+            new: 2
+        }]
+    );
+    assert_eq!(
+        machine.self_modifications()[0].to_string(),
+        "ip 0 wrote 1: 1 -> 2"
+    );
+    assert!(!machine.is_unmodified_code(1));
+    assert!(machine.is_unmodified_code(4));
+    assert!(machine.is_unmodified_code(8));
+
+    let mut untracked = Machine::new(vec![1001, 1, 1, 1, 99]);
+    untracked.run();
+    assert!(untracked.self_modifications().is_empty());
+}
+
+#[test]
+fn day05_reuses_its_first_instruction() {
+    // Day 5 patches code ahead of it freely, but only ever overwrites code
+    // it has already run when it uses address 0 as scratch space.
+    let mut machine = Machine::from_file("data/05").with_self_modification_tracking();
+    assert_eq!(machine.run_with_inputs(&[5]).len(), 1);
+    let writes: Vec<_> = machine
+        .self_modifications()
+        .iter()
+        .map(|m| (m.ip, m.addr))
+        .collect();
+    assert_eq!(writes, vec![(284, 0), (304, 0)]);
+}
+
+#[test]
+fn restore_forgets_modifications() {
+    let mut machine = Machine::new(vec![1001, 1, 1, 1, 99]).with_self_modification_tracking();
+    let start = machine.snapshot();
+    machine.run();
+    assert_eq!(machine.self_modifications().len(), 1);
+    machine.restore(&start);
+    assert!(machine.self_modifications().is_empty());
+    assert!(!machine.is_unmodified_code(0));
+    machine.run();
+    assert_eq!(machine.self_modifications().len(), 1);
+}
+
+#[test]
+fn tracks_code_at_high_addresses() {
+    // Writes a halt far beyond the program and jumps to it.
+    let mut machine = Machine::new(vec![1101, 99, 0, 3_000_000_000, 1105, 1, 3_000_000_000])
+        .with_self_modification_tracking();
+    assert_eq!(machine.run(), 1101);
+    assert!(machine.is_unmodified_code(3_000_000_000));
+    assert!(machine.self_modifications().is_empty());
+}
@@ -0,0 +1,217 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::bigint::BigInt;
+use crate::memory::Memory;
+use crate::Machine;
+
+/// A frozen copy of a machine's memory and registers, without its I/O.
+///
+/// Round-trips through a line-oriented text form via `Display`/`FromStr`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MachineSnapshot {
+    pub ip: usize,
+    pub relative_base: i64,
+    /// Values queued with `provide_input` but not yet consumed.
+    pub inputs: Vec<i64>,
+    pub(crate) memory: Memory,
+    pub(crate) initial_size: usize,
+}
+
+impl MachineSnapshot {
+    /// The original program region, as returned by `Machine::state`.
+    pub fn state(&self) -> &[i64] {
+        &self.memory.dense()[..self.initial_size]
+    }
+}
+
+impl Machine {
+    pub fn snapshot(&self) -> MachineSnapshot {
+        MachineSnapshot {
+            ip: self.ip,
+            relative_base: self.relative_base,
+            inputs: self.inputs.iter().cloned().collect(),
+            memory: self.data.clone(),
+            initial_size: self.initial_size,
+        }
+    }
+
+    /// Rewind (or fast-forward) to `snapshot`. The I/O callbacks are kept.
+    pub fn restore(&mut self, snapshot: &MachineSnapshot) {
+        self.ip = snapshot.ip;
+        self.relative_base = snapshot.relative_base;
+        self.inputs = snapshot.inputs.iter().cloned().collect();
+        self.data = snapshot.memory.clone();
+        self.initial_size = snapshot.initial_size;
+        self.cache.clear();
+        self.exact_output = None;
+        if let Some(tracker) = self.selfmod.as_mut() {
+            **tracker = Default::default();
+        }
+    }
+
+    pub fn from_snapshot_with_io(
+        snapshot: &MachineSnapshot,
+        input: impl FnMut() -> Option<i64> + 'static + Send,
+        output: impl FnMut(i64) + 'static + Send,
+    ) -> Self {
+        let mut machine = Machine::new_with_fallible_io(Vec::new(), input, output);
+        machine.restore(snapshot);
+        machine
+    }
+
+    /// An independent copy of this machine at its current point of execution,
+    /// with the same settings but talking to the world through new I/O
+    /// callbacks.
+    pub fn fork_with_io(
+        &self,
+        input: impl FnMut() -> Option<i64> + 'static + Send,
+        output: impl FnMut(i64) + 'static + Send,
+    ) -> Self {
+        Machine::from_snapshot_with_io(&self.snapshot(), input, output)
+            .with_decoder(self.decoder)
+            .with_arithmetic(self.arithmetic)
+    }
+}
+
+fn join<T: fmt::Display>(vals: impl Iterator<Item = T>) -> String {
+    vals.map(|v| v.to_string()).collect::<Vec<_>>().join(",")
+}
+
+impl fmt::Display for MachineSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "ip {}", self.ip)?;
+        writeln!(f, "relative_base {}", self.relative_base)?;
+        writeln!(f, "initial_size {}", self.initial_size)?;
+        writeln!(f, "limit {}", self.memory.limit())?;
+        writeln!(f, "inputs {}", join(self.inputs.iter()))?;
+        writeln!(f, "dense {}", join(self.memory.dense().iter()))?;
+        let sparse = self
+            .memory
+            .sparse()
+            .iter()
+            .map(|(addr, val)| format!("{}={}", addr, val));
+        writeln!(f, "sparse {}", join(sparse))?;
+        if !self.memory.exact().is_empty() {
+            let exact = self
+                .memory
+                .exact()
+                .iter()
+                .map(|(addr, val)| format!("{}={}", addr, val));
+            writeln!(f, "exact {}", join(exact))?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_num<T: FromStr>(key: &str, val: &str) -> Result<T, String> {
+    val.parse()
+        .map_err(|_| format!("Bad value for {}: {:?}", key, val))
+}
+
+fn parse_list<T: FromStr>(key: &str, val: &str) -> Result<Vec<T>, String> {
+    if val.is_empty() {
+        return Ok(Vec::new());
+    }
+    val.split(',').map(|v| parse_num(key, v)).collect()
+}
+
+/// Parse `addr=val` pairs.
+fn parse_cells<T: FromStr>(key: &str, val: &str) -> Result<BTreeMap<usize, T>, String> {
+    parse_list::<String>(key, val)?
+        .iter()
+        .map(|entry| {
+            let mut iter = entry.splitn(2, '=');
+            let addr = parse_num(key, iter.next().unwrap())?;
+            let val = parse_num(key, iter.next().unwrap_or(""))?;
+            Ok((addr, val))
+        })
+        .collect()
+}
+
+impl FromStr for MachineSnapshot {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = BTreeMap::new();
+        for line in s.lines().filter(|line| !line.trim().is_empty()) {
+            let mut iter = line.splitn(2, ' ');
+            let key = iter.next().unwrap();
+            let val = iter.next().unwrap_or("").trim();
+            fields.insert(key, val);
+        }
+        let field = |key: &str| {
+            fields
+                .get(key)
+                .cloned()
+                .ok_or_else(|| format!("Missing field: {}", key))
+        };
+
+        let sparse = parse_cells("sparse", field("sparse")?)?;
+        let mut memory = Memory::from_parts(
+            parse_list("dense", field("dense")?)?,
+            sparse,
+            parse_num("limit", field("limit")?)?,
+        )?;
+        // Only written for `Arithmetic::Big` values that don't fit an i64.
+        let exact = parse_cells::<BigInt>("exact", fields.get("exact").cloned().unwrap_or(""))?;
+        for (addr, val) in exact {
+            memory
+                .set_big(addr, val)
+                .map_err(|_| format!("Bad address for exact: {}", addr))?;
+        }
+        let initial_size = parse_num("initial_size", field("initial_size")?)?;
+        if initial_size > memory.dense().len() {
+            return Err(format!("initial_size {} exceeds memory", initial_size));
+        }
+        Ok(MachineSnapshot {
+            ip: parse_num("ip", field("ip")?)?,
+            relative_base: parse_num("relative_base", field("relative_base")?)?,
+            inputs: parse_list("inputs", field("inputs")?)?,
+            memory,
+            initial_size,
+        })
+    }
+}
+
+#[test]
+fn snapshot_round_trips_through_text() {
+    let mut machine = Machine::new(vec![3, 9, 1001, 9, 1, 9, 4, 9, 99, 0]).with_memory_limit(4096);
+    machine.provide_input(41);
+    machine.provide_input(5);
+    machine.step_event().unwrap();
+    let snapshot = machine.snapshot();
+    let parsed: MachineSnapshot = snapshot.to_string().parse().unwrap();
+    assert_eq!(parsed, snapshot);
+    assert_eq!(parsed.inputs, vec![5]);
+    let shadowed = snapshot.to_string().replace("sparse ", "sparse 3=7");
+    assert_eq!(
+        shadowed.parse::<MachineSnapshot>(),
+        Err("sparse cell 3 lies inside dense memory of 10 cells".to_string())
+    );
+
+    let mut machine =
+        Machine::new(vec![1002, 5, 1 << 62, 5, 99, 8]).with_arithmetic(crate::Arithmetic::Big);
+    machine.run();
+    let snapshot = machine.snapshot();
+    let parsed: MachineSnapshot = snapshot.to_string().parse().unwrap();
+    assert_eq!(parsed, snapshot);
+    let mut restored = Machine::new(vec![]);
+    restored.restore(&parsed);
+    assert_eq!(restored.peek_exact(5).to_string(), "36893488147419103232");
+}
+
+#[test]
+fn fork_continues_independently() {
+    let mut machine = Machine::new(vec![3, 9, 1001, 9, 1, 9, 4, 9, 99, 0]);
+    machine.provide_input(41);
+    machine.step_event().unwrap();
+    let mut fork = machine.fork_with_io(|| None, |_| ());
+    machine.poke(9, 100);
+
+    assert_eq!(fork.run_until_event(), Ok(crate::Event::Output(42)));
+    assert_eq!(machine.run_until_event(), Ok(crate::Event::Output(101)));
+    assert_eq!(fork.peek(9), 42);
+    assert_eq!(machine.peek(9), 101);
+    assert_ne!(fork.snapshot(), machine.snapshot());
+}
@@ -0,0 +1,824 @@
+//! Symbolic execution: solving for the inputs that produce a result.
+//!
+//! Chosen memory cells and input values are treated as symbols `x0`, `x1`,
+//! ... and the program is run on polynomials over them instead of numbers.
+//! Arithmetic wraps exactly as the interpreter's does, so a polynomial gives
+//! the machine's result for every assignment. A comparison or jump that
+//! depends on a symbol forks the run, each side remembering the condition it
+//! assumed. Reading through a symbolic address gives an unknown value, which
+//! is fine as long as it is overwritten before it matters.
+//!
+//! Solving then only needs polynomial arithmetic: when the result is linear
+//! in some symbol it is solved for directly, otherwise every assignment is
+//! tried on the polynomial. Programs that can't be followed symbolically,
+//! e.g. ones that write through a symbolic address or branch too much, are
+//! searched by running the interpreter on every assignment.
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::{decode, Event, Instr, Machine, Mode, OpNone, OpOne, OpThree, OpTwo};
+
+/// A polynomial over the symbols with wrapping integer coefficients. Each
+/// term is keyed by its symbols in ascending order, repeated for powers.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Poly {
+    terms: BTreeMap<Vec<usize>, i64>,
+}
+
+impl Poly {
+    pub fn constant(val: i64) -> Self {
+        let mut poly = Poly::default();
+        if val != 0 {
+            poly.terms.insert(vec![], val);
+        }
+        poly
+    }
+
+    pub fn symbol(ix: usize) -> Self {
+        let mut poly = Poly::default();
+        poly.terms.insert(vec![ix], 1);
+        poly
+    }
+
+    /// The value, if no symbols are involved.
+    pub fn as_constant(&self) -> Option<i64> {
+        match self.terms.len() {
+            0 => Some(0),
+            1 => self.terms.get(&vec![]).copied(),
+            _ => None,
+        }
+    }
+
+    pub fn degree(&self) -> usize {
+        self.terms.keys().map(|t| t.len()).max().unwrap_or(0)
+    }
+
+    fn add_term(&mut self, term: Vec<usize>, coeff: i64) {
+        let entry = self.terms.entry(term).or_insert(0);
+        *entry = entry.wrapping_add(coeff);
+        if *entry == 0 {
+            self.terms.retain(|_, c| *c != 0);
+        }
+    }
+
+    pub fn add(&self, other: &Poly) -> Poly {
+        let mut sum = self.clone();
+        for (term, &coeff) in &other.terms {
+            sum.add_term(term.clone(), coeff);
+        }
+        sum
+    }
+
+    pub fn sub(&self, other: &Poly) -> Poly {
+        self.add(&other.mul(&Poly::constant(-1)))
+    }
+
+    pub fn mul(&self, other: &Poly) -> Poly {
+        let mut product = Poly::default();
+        for (t1, &c1) in &self.terms {
+            for (t2, &c2) in &other.terms {
+                let mut term: Vec<usize> = t1.iter().chain(t2).copied().collect();
+                term.sort_unstable();
+                product.add_term(term, c1.wrapping_mul(c2));
+            }
+        }
+        product
+    }
+
+    /// The value with `values[i]` for symbol `i`, wrapping like the machine.
+    pub fn eval(&self, values: &[i64]) -> i64 {
+        self.terms.iter().fold(0i64, |acc, (term, &coeff)| {
+            let product = term.iter().fold(coeff, |p, &ix| p.wrapping_mul(values[ix]));
+            acc.wrapping_add(product)
+        })
+    }
+
+    /// The coefficient of `ix`, if the symbol only appears on its own and
+    /// to the first power.
+    fn linear_in(&self, ix: usize) -> Option<i64> {
+        let mut coeff = None;
+        for (term, &c) in &self.terms {
+            if term.as_slice() == [ix] {
+                coeff = Some(c);
+            } else if term.contains(&ix) {
+                return None;
+            }
+        }
+        coeff
+    }
+}
+
+impl fmt::Display for Poly {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.terms.is_empty() {
+            return write!(f, "0");
+        }
+        // Highest degree first, constant last.
+        let mut terms: Vec<_> = self.terms.iter().collect();
+        terms.sort_by_key(|(term, _)| std::cmp::Reverse(term.len()));
+        for (ix, (term, &coeff)) in terms.into_iter().enumerate() {
+            let (sign, abs) = if coeff < 0 {
+                ("-", coeff.unsigned_abs())
+            } else {
+                ("+", coeff as u64)
+            };
+            match (ix, sign) {
+                (0, "-") => write!(f, "-")?,
+                (0, _) => {}
+                _ => write!(f, " {} ", sign)?,
+            }
+            let vars: Vec<_> = term.iter().map(|s| format!("x{}", s)).collect();
+            match (abs, vars.is_empty()) {
+                (_, true) => write!(f, "{}", abs)?,
+                (1, false) => write!(f, "{}", vars.join("*"))?,
+                _ => write!(f, "{}*{}", abs, vars.join("*"))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A condition a path assumed about a polynomial.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Relation {
+    Zero,
+    NonZero,
+    Negative,
+    NonNegative,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Constraint {
+    pub poly: Poly,
+    pub relation: Relation,
+}
+
+impl Constraint {
+    pub fn holds(&self, values: &[i64]) -> bool {
+        let val = self.poly.eval(values);
+        match self.relation {
+            Relation::Zero => val == 0,
+            Relation::NonZero => val != 0,
+            Relation::Negative => val < 0,
+            Relation::NonNegative => val >= 0,
+        }
+    }
+}
+
+/// How a path finished.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum End {
+    /// Halted with this value at address 0, or `None` if it is unknown.
+    Halted(Option<Poly>),
+    /// Wanted more input than was given.
+    NeedInput,
+    /// Hit an instruction the machine would fail on.
+    Fault { ip: usize },
+}
+
+/// One way through the program, for the assignments satisfying every
+/// constraint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Path {
+    pub constraints: Vec<Constraint>,
+    pub outputs: Vec<Poly>,
+    pub end: End,
+}
+
+impl Path {
+    pub fn feasible(&self, values: &[i64]) -> bool {
+        self.constraints.iter().all(|c| c.holds(values))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SymbolicError {
+    /// Something the engine can't follow, like writing through a symbolic
+    /// address.
+    Unsupported {
+        ip: usize,
+        reason: &'static str,
+    },
+    TooManyPaths,
+    StepLimit,
+}
+
+impl fmt::Display for SymbolicError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SymbolicError::Unsupported { ip, reason } => write!(f, "{} at ip {}", reason, ip),
+            SymbolicError::TooManyPaths => write!(f, "Too many paths"),
+            SymbolicError::StepLimit => write!(f, "Instruction limit reached"),
+        }
+    }
+}
+
+impl std::error::Error for SymbolicError {}
+
+/// What the solution should make the program do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Goal {
+    /// Halt with this value at address 0.
+    Halted(i64),
+    /// Halt with this as the last output.
+    Output(i64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    /// Solved for a symbol the result is linear in.
+    Linear,
+    /// Tried every assignment on the symbolic result.
+    Enumerated,
+    /// Ran the interpreter on every assignment.
+    Search,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Solution {
+    /// A value for each symbol, in the order they were declared.
+    pub values: Vec<i64>,
+    pub method: Method,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Val {
+    Known(Poly),
+    /// Read through a symbolic address.
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum InputVal {
+    Value(i64),
+    Symbol(usize),
+}
+
+/// Memory is only followed this far; anything beyond is unknown.
+const MEMORY_CAP: usize = 1 << 20;
+
+#[derive(Debug, Clone)]
+struct State {
+    memory: Vec<Val>,
+    ip: usize,
+    relative_base: i64,
+    inputs_used: usize,
+    constraints: Vec<Constraint>,
+    outputs: Vec<Poly>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Solver {
+    program: Vec<i64>,
+    ranges: Vec<RangeInclusive<i64>>,
+    cells: Vec<(usize, usize)>,
+    inputs: Vec<InputVal>,
+    max_steps: u64,
+    max_paths: usize,
+}
+
+impl Solver {
+    pub fn new(program: Vec<i64>) -> Self {
+        Solver {
+            program,
+            ranges: vec![],
+            cells: vec![],
+            inputs: vec![],
+            max_steps: 1_000_000,
+            max_paths: 256,
+        }
+    }
+
+    /// Make the cell at `addr` the next symbol, taking values in `range`.
+    pub fn with_cell(mut self, addr: usize, range: RangeInclusive<i64>) -> Self {
+        self.cells.push((addr, self.ranges.len()));
+        self.ranges.push(range);
+        self
+    }
+
+    /// Queue a known input value.
+    pub fn with_input(mut self, val: i64) -> Self {
+        self.inputs.push(InputVal::Value(val));
+        self
+    }
+
+    /// Queue an input that is the next symbol, taking values in `range`.
+    pub fn with_symbolic_input(mut self, range: RangeInclusive<i64>) -> Self {
+        self.inputs.push(InputVal::Symbol(self.ranges.len()));
+        self.ranges.push(range);
+        self
+    }
+
+    /// Bound the instructions executed, over all paths together when
+    /// exploring and per assignment when searching.
+    pub fn with_max_steps(mut self, steps: u64) -> Self {
+        self.max_steps = steps;
+        self
+    }
+
+    pub fn with_max_paths(mut self, paths: usize) -> Self {
+        self.max_paths = paths;
+        self
+    }
+
+    /// Run the program symbolically, returning every path through it.
+    pub fn explore(&self) -> Result<Vec<Path>, SymbolicError> {
+        let mut memory: Vec<Val> = self
+            .program
+            .iter()
+            .map(|&w| Val::Known(Poly::constant(w)))
+            .collect();
+        for &(addr, ix) in &self.cells {
+            if addr >= MEMORY_CAP {
+                return Err(SymbolicError::Unsupported {
+                    ip: 0,
+                    reason: "Symbol beyond memory",
+                });
+            }
+            if addr >= memory.len() {
+                memory.resize(addr + 1, Val::Known(Poly::default()));
+            }
+            memory[addr] = Val::Known(Poly::symbol(ix));
+        }
+        let mut todo = vec![State {
+            memory,
+            ip: 0,
+            relative_base: 0,
+            inputs_used: 0,
+            constraints: vec![],
+            outputs: vec![],
+        }];
+        let mut paths = Vec::new();
+        let mut steps = 0;
+        while let Some(mut state) = todo.pop() {
+            let end = loop {
+                steps += 1;
+                if steps > self.max_steps {
+                    return Err(SymbolicError::StepLimit);
+                }
+                if let Some(end) = self.step(&mut state, &mut todo)? {
+                    break end;
+                }
+            };
+            paths.push(Path {
+                constraints: state.constraints,
+                outputs: state.outputs,
+                end,
+            });
+            if paths.len() + todo.len() > self.max_paths {
+                return Err(SymbolicError::TooManyPaths);
+            }
+        }
+        Ok(paths)
+    }
+
+    /// Find values for the symbols that meet `goal`, or `None` if there are
+    /// none. Solutions found symbolically are checked on the interpreter.
+    /// Solving for a linear symbol only finds solutions where that term
+    /// doesn't overflow.
+    pub fn solve(&self, goal: Goal) -> Option<Solution> {
+        let paths = match self.explore() {
+            Ok(paths) => paths,
+            Err(_) => return self.search(goal),
+        };
+        let mut results = Vec::new();
+        for path in &paths {
+            let result = match (&path.end, goal) {
+                (End::Halted(Some(val)), Goal::Halted(target)) => (val, target),
+                (End::Halted(None), Goal::Halted(_)) => return self.search(goal),
+                (End::Halted(_), Goal::Output(target)) => match path.outputs.last() {
+                    Some(val) => (val, target),
+                    None => continue,
+                },
+                _ => continue,
+            };
+            results.push((path, result));
+        }
+        for (path, (val, target)) in results {
+            if let Some(solution) = self.solve_path(path, val, target, goal) {
+                return Some(solution);
+            }
+        }
+        None
+    }
+
+    fn solve_path(&self, path: &Path, val: &Poly, target: i64, goal: Goal) -> Option<Solution> {
+        let linear = (0..self.ranges.len()).find_map(|ix| {
+            let coeff = val.linear_in(ix)?;
+            let range = &self.ranges[ix];
+            let safe = coeff.checked_mul(*range.start()).is_some()
+                && coeff.checked_mul(*range.end()).is_some();
+            Some((ix, coeff)).filter(|_| safe)
+        });
+        match linear {
+            Some((solve_for, coeff)) => {
+                let mut ranges = self.ranges.clone();
+                ranges[solve_for] = 0..=0;
+                let range = &self.ranges[solve_for];
+                let found = assignments(&ranges).find_map(|mut values| {
+                    let rest = val.eval(&values);
+                    let diff = i128::from(target) - i128::from(rest);
+                    let coeff = i128::from(coeff);
+                    if diff % coeff != 0 {
+                        return None;
+                    }
+                    let x = i64::try_from(diff / coeff)
+                        .ok()
+                        .filter(|x| range.contains(x))?;
+                    values[solve_for] = x;
+                    Some(values).filter(|values| self.check(path, val, target, goal, values))
+                });
+                found.map(|values| Solution {
+                    values,
+                    method: Method::Linear,
+                })
+            }
+            None => assignments(&self.ranges)
+                .find(|values| self.check(path, val, target, goal, values))
+                .map(|values| Solution {
+                    values,
+                    method: Method::Enumerated,
+                }),
+        }
+    }
+
+    /// Whether `values` solve the path, confirmed on the interpreter.
+    fn check(&self, path: &Path, val: &Poly, target: i64, goal: Goal, values: &[i64]) -> bool {
+        val.eval(values) == target && path.feasible(values) && self.meets(goal, values)
+    }
+
+    fn meets(&self, goal: Goal, values: &[i64]) -> bool {
+        match (self.run(values), goal) {
+            (Some((halted, _)), Goal::Halted(target)) => halted == target,
+            (Some((_, outputs)), Goal::Output(target)) => outputs.last() == Some(&target),
+            (None, _) => false,
+        }
+    }
+
+    /// Try every assignment on the interpreter.
+    fn search(&self, goal: Goal) -> Option<Solution> {
+        assignments(&self.ranges)
+            .find(|values| self.meets(goal, values))
+            .map(|values| Solution {
+                values,
+                method: Method::Search,
+            })
+    }
+
+    /// Run with concrete values for the symbols, returning the halting value
+    /// and the outputs, or `None` if the machine doesn't halt in time.
+    fn run(&self, values: &[i64]) -> Option<(i64, Vec<i64>)> {
+        let mut machine = Machine::new_with_fallible_io(self.program.clone(), || None, |_| ());
+        for &(addr, ix) in &self.cells {
+            if addr >= machine.memory_limit() {
+                return None;
+            }
+            machine.poke(addr, values[ix]);
+        }
+        for &input in &self.inputs {
+            machine.provide_input(match input {
+                InputVal::Value(val) => val,
+                InputVal::Symbol(ix) => values[ix],
+            });
+        }
+        let mut outputs = Vec::new();
+        for _ in 0..self.max_steps {
+            match machine.step_event() {
+                Ok(Some(Event::Halted(val))) => return Some((val, outputs)),
+                Ok(Some(Event::Output(val))) => outputs.push(val),
+                Ok(None) => {}
+                Ok(Some(Event::NeedInput)) | Err(_) => return None,
+            }
+        }
+        None
+    }
+
+    /// Execute one instruction, pushing the other side of any fork onto
+    /// `todo`. Returns how the path ended, if it did.
+    fn step(&self, state: &mut State, todo: &mut Vec<State>) -> Result<Option<End>, SymbolicError> {
+        let ip = state.ip;
+        let unsupported = |reason| SymbolicError::Unsupported { ip, reason };
+        let word = match load(state, ip as i64) {
+            Some(Val::Known(word)) => word.as_constant(),
+            _ => None,
+        }
+        .ok_or_else(|| unsupported("Symbolic instruction"))?;
+        let instr = match decode(word) {
+            Some(instr) if !instr.writes_immediate() => instr,
+            _ => return Ok(Some(End::Fault { ip })),
+        };
+        let modes = instr.modes();
+        let mut args = Vec::new();
+        for (ix, &mode) in modes.iter().enumerate() {
+            let arg = load(state, (ip + 1 + ix) as i64).unwrap_or(Val::Unknown);
+            args.push((mode, arg));
+        }
+        // Where an operand points, if it is known.
+        let pointer = |state: &State, (mode, arg): &(Mode, Val)| -> Result<Option<i64>, ()> {
+            let offset = match arg {
+                Val::Known(poly) => match poly.as_constant() {
+                    Some(offset) => offset,
+                    None => return Ok(None),
+                },
+                Val::Unknown => return Ok(None),
+            };
+            match mode {
+                Mode::Position => Ok(Some(offset)),
+                Mode::Relative => Ok(Some(state.relative_base.wrapping_add(offset))),
+                Mode::Immediate => Err(()),
+            }
+        };
+        let read = |state: &State, ix: usize| -> Option<Val> {
+            match pointer(state, &args[ix]) {
+                Err(()) => Some(args[ix].1.clone()),
+                Ok(Some(addr)) => load(state, addr),
+                Ok(None) => Some(Val::Unknown),
+            }
+        };
+        let known = |val: Option<Val>, reason| match val {
+            Some(Val::Known(poly)) => Ok(Some(poly)),
+            Some(Val::Unknown) => Err(unsupported(reason)),
+            None => Ok(None),
+        };
+        let next = ip + instr.width();
+
+        macro_rules! operand {
+            ($ix:expr, $reason:expr) => {
+                match known(read(state, $ix), $reason)? {
+                    Some(poly) => poly,
+                    None => return Ok(Some(End::Fault { ip })),
+                }
+            };
+        }
+        macro_rules! target {
+            ($ix:expr) => {
+                match pointer(state, &args[$ix]) {
+                    Ok(Some(addr)) if addr < 0 => return Ok(Some(End::Fault { ip })),
+                    Ok(Some(addr)) if addr as usize >= MEMORY_CAP => {
+                        return Err(unsupported("Write beyond symbolic memory"))
+                    }
+                    Ok(Some(addr)) => addr as usize,
+                    _ => return Err(unsupported("Write through a symbolic address")),
+                }
+            };
+        }
+
+        match instr {
+            Instr::NoneArg(OpNone::Exit) => {
+                let val = match load(state, 0) {
+                    Some(Val::Known(poly)) => Some(poly),
+                    _ => None,
+                };
+                return Ok(Some(End::Halted(val)));
+            }
+            Instr::OneArg(op, _) => match op {
+                OpOne::Input => {
+                    let addr = target!(0);
+                    let val = match self.inputs.get(state.inputs_used) {
+                        Some(InputVal::Value(val)) => Poly::constant(*val),
+                        Some(InputVal::Symbol(ix)) => Poly::symbol(*ix),
+                        None => return Ok(Some(End::NeedInput)),
+                    };
+                    state.inputs_used += 1;
+                    store(state, addr, Val::Known(val));
+                }
+                OpOne::Output => {
+                    let val = operand!(0, "Output of an unknown value");
+                    state.outputs.push(val);
+                }
+                OpOne::AdjustRelativeBase => {
+                    let val = operand!(0, "Unknown relative base");
+                    let adj = val
+                        .as_constant()
+                        .ok_or_else(|| unsupported("Symbolic relative base"))?;
+                    state.relative_base = state.relative_base.wrapping_add(adj);
+                }
+            },
+            Instr::TwoArg(op, _) => {
+                let cond = operand!(0, "Jump on an unknown value");
+                let to = operand!(1, "Jump to an unknown address")
+                    .as_constant()
+                    .ok_or_else(|| unsupported("Jump to a symbolic address"))?;
+                let (taken, not_taken) = match op {
+                    OpTwo::JumpIfTrue => (Relation::NonZero, Relation::Zero),
+                    OpTwo::JumpIfFalse => (Relation::Zero, Relation::NonZero),
+                };
+                let jump_to = |state: &mut State| {
+                    if to < 0 {
+                        return false;
+                    }
+                    state.ip = to as usize;
+                    true
+                };
+                match cond.as_constant() {
+                    Some(c) => {
+                        if (c != 0) == (taken == Relation::NonZero) {
+                            if !jump_to(state) {
+                                return Ok(Some(End::Fault { ip }));
+                            }
+                            return Ok(None);
+                        }
+                    }
+                    None => {
+                        let mut other = state.clone();
+                        other.constraints.push(Constraint {
+                            poly: cond.clone(),
+                            relation: taken,
+                        });
+                        if jump_to(&mut other) {
+                            todo.push(other);
+                        }
+                        state.constraints.push(Constraint {
+                            poly: cond,
+                            relation: not_taken,
+                        });
+                    }
+                }
+            }
+            Instr::ThreeArg(op @ OpThree::Add, _) | Instr::ThreeArg(op @ OpThree::Mul, _) => {
+                let (a, b) = match (read(state, 0), read(state, 1)) {
+                    (Some(a), Some(b)) => (a, b),
+                    _ => return Ok(Some(End::Fault { ip })),
+                };
+                let addr = target!(2);
+                let result = match (a, b) {
+                    (Val::Known(a), Val::Known(b)) if op == OpThree::Add => Val::Known(a.add(&b)),
+                    (Val::Known(a), Val::Known(b)) => Val::Known(a.mul(&b)),
+                    // Only an error if it's used later.
+                    _ => Val::Unknown,
+                };
+                store(state, addr, result);
+            }
+            Instr::ThreeArg(op, _) => {
+                let a = operand!(0, "Comparison of an unknown value");
+                let b = operand!(1, "Comparison of an unknown value");
+                let addr = target!(2);
+                // What the comparison says about a - b.
+                let diff = a.sub(&b);
+                let (yes, no) = match op {
+                    OpThree::LessThan => (Relation::Negative, Relation::NonNegative),
+                    _ => (Relation::Zero, Relation::NonZero),
+                };
+                let result = match diff.as_constant() {
+                    Some(_) => {
+                        let holds = Constraint {
+                            poly: diff,
+                            relation: yes,
+                        }
+                        .holds(&[]);
+                        Poly::constant(holds as i64)
+                    }
+                    None => {
+                        let mut other = state.clone();
+                        other.constraints.push(Constraint {
+                            poly: diff.clone(),
+                            relation: no,
+                        });
+                        store(&mut other, addr, Val::Known(Poly::constant(0)));
+                        other.ip = next;
+                        todo.push(other);
+                        state.constraints.push(Constraint {
+                            poly: diff,
+                            relation: yes,
+                        });
+                        Poly::constant(1)
+                    }
+                };
+                store(state, addr, Val::Known(result));
+            }
+        }
+        state.ip = next;
+        Ok(None)
+    }
+}
+
+/// The value at `addr`, or `None` if the address is negative.
+fn load(state: &State, addr: i64) -> Option<Val> {
+    if addr < 0 {
+        return None;
+    }
+    if addr as usize >= MEMORY_CAP {
+        return Some(Val::Unknown);
+    }
+    Some(
+        state
+            .memory
+            .get(addr as usize)
+            .cloned()
+            .unwrap_or(Val::Known(Poly::default())),
+    )
+}
+
+fn store(state: &mut State, addr: usize, val: Val) {
+    if addr >= state.memory.len() {
+        state.memory.resize(addr + 1, Val::Known(Poly::default()));
+    }
+    state.memory[addr] = val;
+}
+
+/// Every combination of values from `ranges`, the last varying fastest.
+fn assignments(ranges: &[RangeInclusive<i64>]) -> impl Iterator<Item = Vec<i64>> + '_ {
+    let mut next = if ranges.iter().any(|r| r.is_empty()) {
+        None
+    } else {
+        Some(ranges.iter().map(|r| *r.start()).collect::<Vec<_>>())
+    };
+    std::iter::from_fn(move || {
+        let current = next.take()?;
+        let mut following = current.clone();
+        for ix in (0..ranges.len()).rev() {
+            if following[ix] < *ranges[ix].end() {
+                following[ix] += 1;
+                next = Some(following);
+                break;
+            }
+            following[ix] = *ranges[ix].start();
+        }
+        Some(current)
+    })
+}
+
+#[test]
+fn day02_is_linear() {
+    let program = Machine::from_file("data/02").state().to_vec();
+    let solver = Solver::new(program)
+        .with_cell(1, 0..=99)
+        .with_cell(2, 0..=99);
+    let paths = solver.explore().unwrap();
+    assert_eq!(paths.len(), 1);
+    let result = match &paths[0].end {
+        End::Halted(Some(result)) => result.clone(),
+        other => panic!("{:?}", other),
+    };
+    assert_eq!(result.degree(), 1);
+    assert_eq!(result.eval(&[12, 2]), 6730673);
+    let solution = solver.solve(Goal::Halted(19690720)).unwrap();
+    assert_eq!(solution.method, Method::Linear);
+    assert_eq!(
+        Machine::init_from_file("data/02", solution.values[0], solution.values[1]).run(),
+        19690720
+    );
+    assert_eq!(solver.solve(Goal::Halted(-1)), None);
+}
+
+#[test]
+fn forks_on_symbolic_conditions() {
+    // Output 1 if the input equals 8, 0 otherwise.
+    let solver = Solver::new(vec![3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8]).with_symbolic_input(0..=100);
+    let paths = solver.explore().unwrap();
+    assert_eq!(paths.len(), 2);
+    let solution = solver.solve(Goal::Output(1)).unwrap();
+    assert_eq!(solution.values, vec![8]);
+    assert_eq!(solution.method, Method::Enumerated);
+    assert_eq!(solver.solve(Goal::Output(0)).unwrap().values, vec![0]);
+
+    // x0 * x0 - 3 * x1, solved for x1.
+    let program = crate::asm::assemble(
+        "
+            mul a, a, a
+            mul b, #-3, b
+            add a, b, 0
+            hlt
+        a:  db 0
+        b:  db 0
+        ",
+    )
+    .unwrap();
+    let solver = Solver::new(program)
+        .with_cell(13, -10..=10)
+        .with_cell(14, -10..=10);
+    let solution = solver.solve(Goal::Halted(40)).unwrap();
+    assert_eq!(solution.values, vec![-8, 8]);
+    assert_eq!(solution.method, Method::Linear);
+}
+
+#[test]
+fn falls_back_to_search() {
+    // The input picks where `add` writes 10.
+    let solver = Solver::new(vec![3, 5, 1101, 5, 5, 0, 99]).with_symbolic_input(0..=6);
+    assert!(matches!(
+        solver.explore(),
+        Err(SymbolicError::Unsupported { ip: 2, .. })
+    ));
+    let solution = solver.solve(Goal::Halted(10)).unwrap();
+    assert_eq!(solution.values, vec![0]);
+    assert_eq!(solution.method, Method::Search);
+}
+
+#[test]
+fn polynomials_print_readably() {
+    let x = Poly::symbol(0);
+    let y = Poly::symbol(1);
+    let poly = x
+        .mul(&x)
+        .mul(&Poly::constant(2))
+        .sub(&y)
+        .add(&Poly::constant(-7));
+    assert_eq!(poly.to_string(), "2*x0*x0 - x1 - 7");
+    assert_eq!(poly.eval(&[3, 4]), 7);
+    assert_eq!(poly.sub(&poly), Poly::constant(0));
+    assert_eq!(Poly::constant(0).to_string(), "0");
+}
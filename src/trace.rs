@@ -0,0 +1,217 @@
+//! Opt-in instruction tracing and profiling.
+//!
+//! Attach a `Tracer` with `Machine::set_tracer` and it is told about every
+//! instruction the machine executes. A tracer can be a closure, a `Profiler`,
+//! or an `Arc<Mutex<_>>` around either so the caller can keep a handle to it
+//! while the machine runs.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+use crate::decode::decode_word;
+use crate::disasm::format_instr;
+use crate::{Instr, Machine};
+
+/// A record of one executed instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub ip: usize,
+    pub instr: Instr,
+    /// The raw operand words, as stored after the opcode.
+    pub args: Vec<i64>,
+    /// Values of the operands that were read, after resolving their modes.
+    pub reads: Vec<i64>,
+    /// The address and value written, if the instruction wrote to memory.
+    pub write: Option<(usize, i64)>,
+    /// The relative base after the instruction executed.
+    pub relative_base: i64,
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:>5}: {:<32} reads {:?}",
+            self.ip,
+            format_instr(&self.instr, &self.args),
+            self.reads
+        )?;
+        if let Some((addr, val)) = self.write {
+            write!(f, " writes [{}] = {}", addr, val)?;
+        }
+        Ok(())
+    }
+}
+
+pub trait Tracer: Send {
+    fn trace(&mut self, entry: &TraceEntry);
+}
+
+impl<F: FnMut(&TraceEntry) + Send> Tracer for F {
+    fn trace(&mut self, entry: &TraceEntry) {
+        self(entry)
+    }
+}
+
+impl<T: Tracer> Tracer for Arc<Mutex<T>> {
+    fn trace(&mut self, entry: &TraceEntry) {
+        self.lock().unwrap().trace(entry)
+    }
+}
+
+/// Write each entry as a line of text, e.g. to stderr or a file.
+pub struct WriteTracer<W>(pub W);
+
+impl<W: Write + Send> Tracer for WriteTracer<W> {
+    fn trace(&mut self, entry: &TraceEntry) {
+        writeln!(self.0, "{}", entry).expect("Failed to write trace");
+    }
+}
+
+/// Counts how often each address and each opcode is executed.
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    steps: u64,
+    address_hits: BTreeMap<usize, u64>,
+    opcode_counts: BTreeMap<&'static str, u64>,
+}
+
+impl Profiler {
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn hits(&self, addr: usize) -> u64 {
+        self.address_hits.get(&addr).cloned().unwrap_or(0)
+    }
+
+    /// Executions per mnemonic.
+    pub fn opcode_counts(&self) -> &BTreeMap<&'static str, u64> {
+        &self.opcode_counts
+    }
+
+    /// The `n` most executed instruction addresses, busiest first.
+    pub fn hottest(&self, n: usize) -> Vec<(usize, u64)> {
+        let mut hits: Vec<_> = self
+            .address_hits
+            .iter()
+            .map(|(&addr, &count)| (addr, count))
+            .collect();
+        hits.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        hits.truncate(n);
+        hits
+    }
+}
+
+impl Tracer for Profiler {
+    fn trace(&mut self, entry: &TraceEntry) {
+        self.steps += 1;
+        *self.address_hits.entry(entry.ip).or_insert(0) += 1;
+        *self
+            .opcode_counts
+            .entry(entry.instr.mnemonic())
+            .or_insert(0) += 1;
+    }
+}
+
+impl fmt::Display for Profiler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} instructions executed", self.steps)?;
+        writeln!(f, "By opcode:")?;
+        for (mnem, count) in &self.opcode_counts {
+            writeln!(f, "  {:<4} {:>12}", mnem, count)?;
+        }
+        writeln!(f, "Hottest addresses:")?;
+        for (addr, count) in self.hottest(10) {
+            writeln!(f, "  {:>5} {:>12}", addr, count)?;
+        }
+        Ok(())
+    }
+}
+
+impl Machine {
+    /// Report every executed instruction to `tracer`, replacing any tracer
+    /// already attached.
+    pub fn set_tracer(&mut self, tracer: impl Tracer + 'static) {
+        self.tracer = Some(Box::new(tracer));
+    }
+
+    pub fn clear_tracer(&mut self) {
+        self.tracer = None;
+    }
+
+    pub(crate) fn trace(&mut self, ip: usize, word: i64) {
+        let instr = decode_word(word).expect("Traced instruction must decode");
+        let args = (1..instr.width())
+            .map(|ix| self.data.get(ip + ix).unwrap_or(0))
+            .collect();
+        let entry = TraceEntry {
+            ip,
+            instr,
+            args,
+            reads: self.effects.reads[..self.effects.nreads].to_vec(),
+            write: self.effects.write,
+            relative_base: self.relative_base,
+        };
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.trace(&entry);
+        }
+    }
+}
+
+#[test]
+fn traces_reads_and_writes() {
+    let entries = Arc::new(Mutex::new(Vec::new()));
+    let sink = entries.clone();
+    let mut machine = Machine::new(vec![1002, 4, 3, 4, 33]);
+    machine.set_tracer(move |entry: &TraceEntry| sink.lock().unwrap().push(entry.clone()));
+    machine.run();
+
+    let entries = entries.lock().unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].reads, vec![33, 3]);
+    assert_eq!(entries[0].write, Some((4, 99)));
+    assert_eq!(
+        entries[0].to_string(),
+        "    0: mul 4, #3, 4                     reads [33, 3] writes [4] = 99"
+    );
+    assert_eq!(entries[1].instr.mnemonic(), "hlt");
+}
+
+#[test]
+fn profiles_loops() {
+    // Count down from 10 to zero.
+    let program = crate::asm::assemble(
+        "
+        loop:   add n, #-1, n
+                jt n, #loop
+                hlt
+        n:      db 10
+        ",
+    )
+    .unwrap();
+    let profile = Arc::new(Mutex::new(Profiler::default()));
+    let mut machine = Machine::new(program);
+    machine.set_tracer(profile.clone());
+    machine.run();
+
+    let profile = profile.lock().unwrap();
+    assert_eq!(profile.steps(), 21);
+    assert_eq!(profile.hits(0), 10);
+    assert_eq!(profile.opcode_counts()["jt"], 10);
+    assert_eq!(profile.hottest(2), vec![(0, 10), (4, 10)]);
+}
+
+#[test]
+fn profiles_code_at_high_addresses() {
+    // Writes a halt far beyond the program and jumps to it.
+    let profile = Arc::new(Mutex::new(Profiler::default()));
+    let mut machine = Machine::new(vec![1101, 99, 0, 3_000_000_000, 1105, 1, 3_000_000_000]);
+    machine.set_tracer(profile.clone());
+    machine.run();
+    let profile = profile.lock().unwrap();
+    assert_eq!(profile.hits(3_000_000_000), 1);
+    assert_eq!(profile.hottest(5), vec![(0, 1), (4, 1), (3_000_000_000, 1)]);
+}